@@ -0,0 +1,440 @@
+//! Backgrounds and borders of widget annotations, as described by their appearance
+//! characteristics (`/MK`), border style (`/BS`) and border (`/Border`)
+
+use lopdf::content::{Content, Operation};
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream, StringFormat};
+
+use crate::default_appearance::DefaultAppearance;
+use crate::metrics::{Metrics, ZAPF_DINGBATS};
+use crate::utils::{font_entry, get_acroform, get_rect, number};
+
+/// The color Acrobat highlights the selected options of list boxes with
+pub const HIGHLIGHT: [f32; 3] = [0.6, 0.757, 0.855];
+
+/// What the appearance of a text field or choice field shows
+pub enum Contents<'a> {
+    /// The value of a text field or combo box
+    Text(&'a str),
+    /// The options of a list box starting at the option `top_index` (`/TI`), with the selected
+    /// ones highlighted
+    List {
+        options: &'a [String],
+        selected: &'a [String],
+        top_index: usize,
+    },
+}
+
+/// The ZapfDingbats character of the default caption of check boxes, a check mark.  The other
+/// captions Acrobat offers are a cross (`8`), a diamond (`u`), a square (`n`) and a star (`H`)
+const CHECK: u8 = b'4';
+
+/// The ZapfDingbats character of the default caption of radio buttons, a circle
+const CIRCLE: u8 = b'l';
+
+/// The style of the border of a widget (`/BS /S`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorderStyle {
+    Solid,
+    Dashed,
+    /// Looks embossed, lighter at the top left and darker at the bottom right
+    Beveled,
+    /// Looks engraved, darker at the top left and lighter at the bottom right
+    Inset,
+    /// Only a line at the bottom
+    Underline,
+}
+
+/// The background and border drawn around the content of a widget
+pub struct Frame {
+    /// The width of the border.  No border is drawn when it is 0
+    pub width: f32,
+    pub style: BorderStyle,
+    /// The dash pattern of dashed borders
+    pub dash: Vec<f32>,
+    /// The color of the border (`/MK /BC`).  No border line is drawn without one
+    pub border_color: Option<Vec<f32>>,
+    /// The color of the background (`/MK /BG`).  The background is transparent without one
+    pub background: Option<Vec<f32>>,
+}
+
+impl Frame {
+    /// Reads the frame of `widget`.  The border style (`/BS`) takes precedence over the older
+    /// border array (`/Border`)
+    pub fn of_widget(doc: &Document, widget: &Dictionary) -> Self {
+        let deref = |object: &Object| doc.dereference(object).map(|(_, object)| object.clone());
+        let number_of = |object: &Object| {
+            deref(object)
+                .and_then(|object| {
+                    object
+                        .as_f64()
+                        .or_else(|_| object.as_i64().map(|x| x as f64))
+                })
+                .map(|x| x as f32)
+                .ok()
+        };
+        let numbers = |object: Option<Object>| -> Option<Vec<f32>> {
+            match object {
+                Some(Object::Array(array)) => array.iter().map(number_of).collect(),
+                _ => None,
+            }
+        };
+
+        let mut frame = Frame {
+            width: 1.0,
+            style: BorderStyle::Solid,
+            dash: vec![3.0],
+            border_color: None,
+            background: None,
+        };
+
+        if let Ok(Object::Dictionary(bs)) = widget.get(b"BS").and_then(deref) {
+            if let Some(width) = bs.get(b"W").ok().and_then(number_of) {
+                frame.width = width;
+            }
+            frame.style = match bs.get(b"S").and_then(Object::as_name) {
+                Ok(b"D") => BorderStyle::Dashed,
+                Ok(b"B") => BorderStyle::Beveled,
+                Ok(b"I") => BorderStyle::Inset,
+                Ok(b"U") => BorderStyle::Underline,
+                _ => BorderStyle::Solid,
+            };
+            if let Some(dash) = numbers(bs.get(b"D").and_then(deref).ok()) {
+                frame.dash = dash;
+            }
+        } else if let Ok(Object::Array(border)) = widget.get(b"Border").and_then(deref) {
+            // The horizontal and vertical corner radii are not supported
+            if let Some(width) = border.get(2).and_then(number_of) {
+                frame.width = width;
+            }
+            if let Some(dash) = numbers(border.get(3).cloned()) {
+                frame.style = BorderStyle::Dashed;
+                frame.dash = dash;
+            }
+        }
+
+        if let Ok(Object::Dictionary(mk)) = widget.get(b"MK").and_then(deref) {
+            frame.border_color = numbers(mk.get(b"BC").and_then(deref).ok());
+            frame.background = numbers(mk.get(b"BG").and_then(deref).ok());
+        }
+
+        // Colors without components are transparent
+        frame.border_color = frame.border_color.filter(|color| !color.is_empty());
+        frame.background = frame.background.filter(|color| !color.is_empty());
+
+        frame
+    }
+
+    /// The distance between the edges of the widget and its content: the border, which is twice
+    /// as wide for beveled and inset borders, and 1pt of space
+    pub fn padding(&self) -> f32 {
+        match self.style {
+            BorderStyle::Beveled | BorderStyle::Inset => 1.0 + 2.0 * self.width,
+            _ => 1.0 + self.width,
+        }
+    }
+
+    /// Draws the background and the border for a widget `width` by `height` large
+    pub fn draw(&self, width: f32, height: f32) -> Vec<Operation> {
+        let mut ops = Vec::new();
+
+        if let Some(background) = &self.background {
+            ops.push(Operation::new("q", vec![]));
+            ops.push(fill_color(background));
+            ops.push(rectangle(0.0, 0.0, width, height));
+            ops.push(Operation::new("f", vec![]));
+            ops.push(Operation::new("Q", vec![]));
+        }
+
+        let w = self.width;
+        if w <= 0.0 {
+            return ops;
+        }
+
+        if let Some(border_color) = &self.border_color {
+            ops.push(Operation::new("q", vec![]));
+            ops.push(stroke_color(border_color));
+            ops.push(Operation::new("w", vec![number(w)]));
+            if self.style == BorderStyle::Underline {
+                ops.push(Operation::new("m", vec![0.into(), number(0.5 * w)]));
+                ops.push(Operation::new("l", vec![number(width), number(0.5 * w)]));
+            } else {
+                if self.style == BorderStyle::Dashed {
+                    ops.push(Operation::new(
+                        "d",
+                        vec![
+                            self.dash
+                                .iter()
+                                .map(|&x| number(x))
+                                .collect::<Vec<_>>()
+                                .into(),
+                            0.into(),
+                        ],
+                    ));
+                }
+                ops.push(rectangle(0.5 * w, 0.5 * w, width - w, height - w));
+            }
+            ops.push(Operation::new("S", vec![]));
+            ops.push(Operation::new("Q", vec![]));
+        }
+
+        // Beveled and inset borders have a lighter and a darker half inside the border line
+        let halves = match self.style {
+            BorderStyle::Beveled => Some((
+                vec![1.0],
+                match &self.background {
+                    Some(background) => background.iter().map(|x| 0.5 * x).collect(),
+                    None => vec![0.75],
+                },
+            )),
+            BorderStyle::Inset => Some((vec![0.5], vec![0.75])),
+            _ => None,
+        };
+        if let Some((top_left, bottom_right)) = halves {
+            ops.push(Operation::new("q", vec![]));
+            ops.push(fill_color(&top_left));
+            ops.extend(polygon(&[
+                (w, w),
+                (w, height - w),
+                (width - w, height - w),
+                (width - 2.0 * w, height - 2.0 * w),
+                (2.0 * w, height - 2.0 * w),
+                (2.0 * w, 2.0 * w),
+            ]));
+            ops.push(fill_color(&bottom_right));
+            ops.extend(polygon(&[
+                (width - w, height - w),
+                (width - w, w),
+                (w, w),
+                (2.0 * w, 2.0 * w),
+                (width - 2.0 * w, 2.0 * w),
+                (width - 2.0 * w, height - 2.0 * w),
+            ]));
+            ops.push(Operation::new("Q", vec![]));
+        }
+
+        ops
+    }
+}
+
+/// Gets the normal appearance stream (`/AP /N`) of the widget `widget_id`.  If the widget has
+/// none, an empty Form XObject the size of `rect` is created and attached to it.  Its resources
+/// are the fonts of the default resources (`/DR`) of the form
+pub fn normal_appearance(
+    doc: &mut Document,
+    widget_id: ObjectId,
+    rect: [f32; 4],
+) -> lopdf::Result<ObjectId> {
+    let widget = doc.get_dictionary(widget_id)?;
+    let appearance = widget
+        .get(b"AP")
+        .and_then(|ap| doc.dereference(ap))
+        .and_then(|(_, ap)| ap.as_dict())
+        .ok()
+        .cloned();
+
+    if let Some(Ok(Object::Reference(stream_id))) = appearance.as_ref().map(|ap| ap.get(b"N")) {
+        if doc
+            .get_object(*stream_id)
+            .and_then(Object::as_stream)
+            .is_ok()
+        {
+            return Ok(*stream_id);
+        }
+    }
+
+    let fonts = get_acroform(doc)
+        .and_then(|acroform| acroform.get(b"DR").ok())
+        .and_then(|dr| doc.dereference(dr).ok())
+        .and_then(|(_, dr)| dr.as_dict().ok()?.get(b"Font").ok())
+        .and_then(|fonts| doc.dereference(fonts).ok())
+        .and_then(|(_, fonts)| fonts.as_dict().ok())
+        .cloned()
+        .unwrap_or_default();
+
+    let (width, height) = (rect[2] - rect[0], rect[3] - rect[1]);
+    let stream = Stream::new(
+        dictionary! {
+            "Type" => "XObject",
+            "Subtype" => "Form",
+            "BBox" => vec![0.into(), 0.into(), number(width), number(height)],
+            "Matrix" => vec![1.into(), 0.into(), 0.into(), 1.into(), 0.into(), 0.into()],
+            "Resources" => dictionary! { "Font" => fonts },
+        },
+        Vec::new(),
+    );
+    let stream_id = doc.add_object(stream);
+
+    // Other appearances, such as the down appearance, are kept
+    let mut appearance = appearance.unwrap_or_default();
+    appearance.set("N", stream_id);
+    doc.get_object_mut(widget_id)?
+        .as_dict_mut()?
+        .set("AP", appearance);
+
+    Ok(stream_id)
+}
+
+/// Generates the normal appearances (`/AP /N`) of the on and off states of a check box or radio
+/// button widget, unless the widget already has both.  The on state is the one the widget
+/// already has, or else `on`.  It shows the caption (`/MK /CA`) in ZapfDingbats over the
+/// background and border of the widget, the off state only the background and border
+pub fn button_appearances(
+    doc: &mut Document,
+    widget_id: ObjectId,
+    on: &str,
+    radio: bool,
+) -> lopdf::Result<()> {
+    let widget = doc.get_dictionary(widget_id)?;
+    let rect = get_rect(widget).ok_or(lopdf::Error::DictKey)?;
+
+    let mut appearance = widget
+        .get(b"AP")
+        .and_then(|ap| doc.dereference(ap))
+        .and_then(|(_, ap)| ap.as_dict())
+        .cloned()
+        .unwrap_or_default();
+    let mut states = appearance
+        .get(b"N")
+        .and_then(|n| doc.dereference(n))
+        .and_then(|(_, n)| n.as_dict())
+        .cloned()
+        .unwrap_or_default();
+
+    let on = states
+        .iter()
+        .map(|(state, _)| state)
+        .find(|&state| state != b"Off")
+        .cloned()
+        .unwrap_or_else(|| on.as_bytes().to_vec());
+    if states.has(&on) && states.has(b"Off") {
+        return Ok(());
+    }
+
+    let frame = Frame::of_widget(doc, widget);
+    let da = DefaultAppearance::of(doc, widget).unwrap_or_default();
+    let caption = widget
+        .get(b"MK")
+        .and_then(|mk| doc.dereference(mk))
+        .and_then(|(_, mk)| mk.as_dict()?.get(b"CA"))
+        .ok()
+        .and_then(|ca| match ca {
+            Object::String(ca, _) => ca.first().copied(),
+            _ => None,
+        })
+        .unwrap_or(if radio { CIRCLE } else { CHECK });
+
+    // The caption is sized to fill the room inside the padding unless the default appearance
+    // has a size.  The glyphs of ZapfDingbats are about 0.7 em high
+    let (width, height) = (rect[2] - rect[0], rect[3] - rect[1]);
+    let room = width.min(height) - 2.0 * frame.padding();
+    let caption_width = Metrics::WinAnsi(&ZAPF_DINGBATS).char_width(caption as char) / 1000.0;
+    let font_size = if da.font_size > 0.0 {
+        da.font_size
+    } else {
+        (room / caption_width.max(0.7)).max(0.0)
+    };
+    let x = 0.5 * (width - caption_width * font_size);
+    let y = 0.5 * height - 0.35 * font_size;
+
+    // The ZapfDingbats of the form is preferred to a font of our own
+    let font = get_acroform(doc)
+        .and_then(|acroform| font_entry(doc, acroform.get(b"DR").ok(), "ZaDb"))
+        .cloned()
+        .unwrap_or_else(|| {
+            Object::Dictionary(dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type1",
+                "BaseFont" => "ZapfDingbats",
+            })
+        });
+
+    let mut on_content = Content {
+        operations: frame.draw(width, height),
+    };
+    on_content.operations.append(&mut vec![
+        Operation::new("q", vec![]),
+        Operation::new("BT", vec![]),
+        Operation::new("Tf", vec!["ZaDb".into(), number(font_size)]),
+        da.color_operation(),
+        Operation::new(
+            "Tm",
+            vec![1.into(), 0.into(), 0.into(), 1.into(), number(x), number(y)],
+        ),
+        Operation::new(
+            "Tj",
+            vec![Object::String(vec![caption], StringFormat::Literal)],
+        ),
+        Operation::new("ET", vec![]),
+        Operation::new("Q", vec![]),
+    ]);
+    let off_content = Content {
+        operations: frame.draw(width, height),
+    };
+
+    for (state, content) in [(on, on_content), (b"Off".to_vec(), off_content)] {
+        if states.has(&state) {
+            continue;
+        }
+
+        let mut stream = Stream::new(
+            dictionary! {
+                "Type" => "XObject",
+                "Subtype" => "Form",
+                "BBox" => vec![0.into(), 0.into(), number(width), number(height)],
+                "Matrix" => vec![1.into(), 0.into(), 0.into(), 1.into(), 0.into(), 0.into()],
+                "Resources" => dictionary! {
+                    "Font" => dictionary! { "ZaDb" => font.clone() },
+                },
+            },
+            content.encode()?,
+        );
+        let _ = stream.compress();
+        states.set(state, doc.add_object(stream));
+    }
+
+    appearance.set("N", states);
+    doc.get_object_mut(widget_id)?
+        .as_dict_mut()?
+        .set("AP", appearance);
+
+    Ok(())
+}
+
+/// Sets the fill color with the operator that matches the number of components
+pub fn fill_color(components: &[f32]) -> Operation {
+    let operator = match components.len() {
+        4 => "k",
+        3 => "rg",
+        _ => "g",
+    };
+    Operation::new(operator, components.iter().map(|&x| number(x)).collect())
+}
+
+/// Sets the stroke color with the operator that matches the number of components
+pub fn stroke_color(components: &[f32]) -> Operation {
+    let operator = match components.len() {
+        4 => "K",
+        3 => "RG",
+        _ => "G",
+    };
+    Operation::new(operator, components.iter().map(|&x| number(x)).collect())
+}
+
+/// Appends a rectangle to the path
+pub fn rectangle(x: f32, y: f32, width: f32, height: f32) -> Operation {
+    Operation::new(
+        "re",
+        vec![number(x), number(y), number(width), number(height)],
+    )
+}
+
+/// Fills the polygon through `points`
+fn polygon(points: &[(f32, f32)]) -> Vec<Operation> {
+    let mut ops = Vec::new();
+    for (i, &(x, y)) in points.iter().enumerate() {
+        let operator = if i == 0 { "m" } else { "l" };
+        ops.push(Operation::new(operator, vec![number(x), number(y)]));
+    }
+    ops.push(Operation::new("f", vec![]));
+    ops
+}
@@ -0,0 +1,205 @@
+//! Default appearance strings (`/DA`), which set the font and color of the text of a field
+
+use lopdf::content::{Content, Operation};
+use lopdf::{Dictionary, Document, Object};
+
+use crate::utils::{get_acroform, get_inheritable, number};
+
+/// The color space of the text color of a default appearance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// One component, set with `g`
+    Gray,
+    /// Three components, set with `rg`
+    Rgb,
+    /// Four components, set with `k`
+    Cmyk,
+}
+
+impl ColorSpace {
+    /// The operator that sets the fill color in this color space
+    pub fn operator(self) -> &'static str {
+        match self {
+            ColorSpace::Gray => "g",
+            ColorSpace::Rgb => "rg",
+            ColorSpace::Cmyk => "k",
+        }
+    }
+
+    /// The number of components of a color in this color space
+    pub fn components(self) -> usize {
+        match self {
+            ColorSpace::Gray => 1,
+            ColorSpace::Rgb => 3,
+            ColorSpace::Cmyk => 4,
+        }
+    }
+}
+
+/// The font and text color of a field, parsed from its default appearance string, e.g.
+/// `/Helv 9.5 Tf 0.5 0.5 0.5 rg`
+#[derive(Debug, Clone, PartialEq)]
+pub struct DefaultAppearance {
+    /// The name of the font in the resources, e.g. `Helv`
+    pub font: String,
+    /// The font size.  A size of 0 means the text is sized to fit the field
+    pub font_size: f32,
+    /// The color space of the text color
+    pub color_space: ColorSpace,
+    /// The components of the text color, as many as the color space has
+    pub color: Vec<f32>,
+}
+
+impl Default for DefaultAppearance {
+    /// 12pt Helvetica in black, as `/Helv 12 Tf 0 g`
+    fn default() -> Self {
+        DefaultAppearance {
+            font: "Helv".to_owned(),
+            font_size: 12.0,
+            color_space: ColorSpace::Gray,
+            color: vec![0.0],
+        }
+    }
+}
+
+impl DefaultAppearance {
+    /// Parses a default appearance string.  The last `Tf` and color operators win, other
+    /// operators are ignored, and whatever is missing or malformed keeps its default
+    pub fn parse(da: &[u8]) -> Self {
+        let mut appearance = DefaultAppearance::default();
+        let operations = match Content::decode(da) {
+            Ok(content) => content.operations,
+            Err(_) => return appearance,
+        };
+
+        for operation in operations {
+            let operands = operation
+                .operands
+                .iter()
+                .map(|operand| {
+                    operand
+                        .as_f64()
+                        .or_else(|_| operand.as_i64().map(|x| x as f64))
+                        .map(|x| x as f32)
+                        .ok()
+                })
+                .collect::<Vec<_>>();
+
+            match operation.operator.as_str() {
+                "Tf" => {
+                    if let [Object::Name(font), _] = operation.operands.as_slice() {
+                        if let (Ok(font), Some(font_size)) =
+                            (String::from_utf8(font.clone()), operands[1])
+                        {
+                            appearance.font = font;
+                            appearance.font_size = font_size;
+                        }
+                    }
+                }
+                operator => {
+                    let color_space = match operator {
+                        "g" => ColorSpace::Gray,
+                        "rg" => ColorSpace::Rgb,
+                        "k" => ColorSpace::Cmyk,
+                        _ => continue,
+                    };
+                    let color = operands.into_iter().collect::<Option<Vec<_>>>();
+                    if let Some(color) =
+                        color.filter(|color| color.len() == color_space.components())
+                    {
+                        appearance.color_space = color_space;
+                        appearance.color = color;
+                    }
+                }
+            }
+        }
+
+        appearance
+    }
+
+    /// Gets the default appearance of a field or widget, which may be inherited, or else the one
+    /// of the form.  Returns `None` if neither has one
+    pub fn of(doc: &Document, dict: &Dictionary) -> Option<Self> {
+        let da = match get_inheritable(doc, dict, b"DA") {
+            Ok(da) => da,
+            Err(_) => doc.dereference(get_acroform(doc)?.get(b"DA").ok()?).ok()?.1,
+        };
+
+        match da {
+            Object::String(bytes, _) => Some(DefaultAppearance::parse(bytes)),
+            _ => None,
+        }
+    }
+
+    /// The operator that sets the text color
+    pub fn color_operation(&self) -> Operation {
+        Operation::new(
+            self.color_space.operator(),
+            self.color.iter().map(|&x| number(x)).collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fractional_sizes() {
+        let da = DefaultAppearance::parse(b"/Helv 9.5 Tf 0.5 0.5 0.5 rg");
+        assert_eq!(da.font, "Helv");
+        assert_eq!(da.font_size, 9.5);
+        assert_eq!(da.color_space, ColorSpace::Rgb);
+        assert_eq!(da.color, vec![0.5, 0.5, 0.5]);
+    }
+
+    #[test]
+    fn parses_integer_sizes_and_cmyk_colors() {
+        let da = DefaultAppearance::parse(b"/F1 10 Tf 0 0 1 0 k");
+        assert_eq!(da.font, "F1");
+        assert_eq!(da.font_size, 10.0);
+        assert_eq!(da.color_space, ColorSpace::Cmyk);
+        assert_eq!(da.color, vec![0.0, 0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn keeps_the_size_zero_for_auto_sizing() {
+        let da = DefaultAppearance::parse(b"/Helv 0 Tf 0 g");
+        assert_eq!(da.font_size, 0.0);
+        assert_eq!(da.color_space, ColorSpace::Gray);
+    }
+
+    #[test]
+    fn ignores_other_operators_and_takes_the_last() {
+        let da = DefaultAppearance::parse(
+            b"0 0 1 0 k /Tx BMC /TiRo 11.5 Tf 2 Tz 0.2 0.6 0.2 rg EMC /Cour 8 Tf",
+        );
+        assert_eq!(da.font, "Cour");
+        assert_eq!(da.font_size, 8.0);
+        assert_eq!(da.color_space, ColorSpace::Rgb);
+        assert_eq!(da.color, vec![0.2, 0.6, 0.2]);
+    }
+
+    #[test]
+    fn keeps_defaults_for_what_is_malformed() {
+        assert_eq!(DefaultAppearance::parse(b""), DefaultAppearance::default());
+
+        // Wrong number of components, and a font size that is not a number
+        let da = DefaultAppearance::parse(b"/Helv /Big Tf 0.5 0.5 rg 1 0 0 0 0 k");
+        assert_eq!(da, DefaultAppearance::default());
+
+        let da = DefaultAppearance::parse(b"/TiRo 7 Tf 1 1 g");
+        assert_eq!(da.font, "TiRo");
+        assert_eq!(da.font_size, 7.0);
+        assert_eq!(da.color_space, ColorSpace::Gray);
+        assert_eq!(da.color, vec![0.0]);
+    }
+
+    #[test]
+    fn makes_the_color_operation() {
+        let da = DefaultAppearance::parse(b"/Helv 12 Tf 0 0.5 1 rg");
+        let operation = da.color_operation();
+        assert_eq!(operation.operator, "rg");
+        assert_eq!(operation.operands.len(), 3);
+    }
+}
@@ -0,0 +1,225 @@
+//! Conversion between Rust strings and PDF text strings
+//!
+//! Text strings such as field names and values are either encoded in UTF-16BE, starting with the
+//! byte order mark `FE FF`, or in PDFDocEncoding.  PDF 2.0 additionally allows UTF-8 starting with
+//! the byte order mark `EF BB BF`.
+
+/// The characters of the PDFDocEncoding codes `0x18..=0x1F`, which differ from Latin-1
+const PDF_DOC_LOW: [char; 8] = [
+    '\u{02D8}', '\u{02C7}', '\u{02C6}', '\u{02D9}', '\u{02DD}', '\u{02DB}', '\u{02DA}', '\u{02DC}',
+];
+
+/// The characters of the PDFDocEncoding codes `0x80..=0xA0`, which differ from Latin-1
+const PDF_DOC_HIGH: [char; 33] = [
+    '\u{2022}', '\u{2020}', '\u{2021}', '\u{2026}', '\u{2014}', '\u{2013}', '\u{0192}', '\u{2044}',
+    '\u{2039}', '\u{203A}', '\u{2212}', '\u{2030}', '\u{201E}', '\u{201C}', '\u{201D}', '\u{2018}',
+    '\u{2019}', '\u{201A}', '\u{2122}', '\u{FB01}', '\u{FB02}', '\u{0141}', '\u{0152}', '\u{0160}',
+    '\u{0178}', '\u{017D}', '\u{0131}', '\u{0142}', '\u{0153}', '\u{0161}', '\u{017E}', '\u{FFFD}',
+    '\u{20AC}',
+];
+
+/// Decodes a single PDFDocEncoding byte
+fn pdf_doc_char(byte: u8) -> char {
+    match byte {
+        0x18..=0x1F => PDF_DOC_LOW[(byte - 0x18) as usize],
+        0x7F => '\u{FFFD}',
+        0x80..=0xA0 => PDF_DOC_HIGH[(byte - 0x80) as usize],
+        // The remaining codes are the same as in Latin-1
+        _ => byte as char,
+    }
+}
+
+/// Decodes the bytes of a PDF text string.  Returns `None` if the string claims to be UTF-16BE or
+/// UTF-8 but is not valid
+pub fn decode_text_string(bytes: &[u8]) -> Option<String> {
+    if let Some(utf16) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        if utf16.len() % 2 != 0 {
+            return None;
+        }
+
+        let units = utf16
+            .chunks(2)
+            .map(|unit| u16::from_be_bytes([unit[0], unit[1]]));
+        std::char::decode_utf16(units)
+            .collect::<Result<String, _>>()
+            .ok()
+    } else if let Some(utf8) = bytes.strip_prefix(&[0xEF, 0xBB, 0xBF]) {
+        String::from_utf8(utf8.to_vec()).ok()
+    } else {
+        Some(bytes.iter().map(|&byte| pdf_doc_char(byte)).collect())
+    }
+}
+
+/// Encodes a single character in PDFDocEncoding, if it can be
+fn pdf_doc_byte(c: char) -> Option<u8> {
+    if let Some(i) = PDF_DOC_LOW.iter().position(|&x| x == c) {
+        return Some(0x18 + i as u8);
+    }
+    if let Some(i) = PDF_DOC_HIGH.iter().position(|&x| x == c && x != '\u{FFFD}') {
+        return Some(0x80 + i as u8);
+    }
+
+    match c as u32 {
+        code @ (0x00..=0x17 | 0x20..=0x7E | 0xA1..=0xFF) => Some(code as u8),
+        _ => None,
+    }
+}
+
+/// Encodes a string as PDF text string.  PDFDocEncoding is used when it covers every character,
+/// UTF-16BE with a byte order mark otherwise
+pub fn encode_text_string(text: &str) -> Vec<u8> {
+    match text.chars().map(pdf_doc_byte).collect::<Option<Vec<_>>>() {
+        Some(bytes) => bytes,
+        None => {
+            let mut bytes = vec![0xFE, 0xFF];
+            for unit in text.encode_utf16() {
+                bytes.extend_from_slice(&unit.to_be_bytes());
+            }
+            bytes
+        }
+    }
+}
+
+/// The characters of the WinAnsiEncoding codes `0x80..=0x9F`, which differ from Latin-1.  Unused
+/// codes are marked with `U+FFFD`
+const WIN_ANSI_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{FFFD}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{FFFD}', '\u{017D}', '\u{FFFD}',
+    '\u{FFFD}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{FFFD}', '\u{017E}', '\u{0178}',
+];
+
+/// Encodes a single character in WinAnsiEncoding, if it can be
+pub fn win_ansi_byte(c: char) -> Option<u8> {
+    if let Some(i) = WIN_ANSI_HIGH
+        .iter()
+        .position(|&x| x == c && x != '\u{FFFD}')
+    {
+        return Some(0x80 + i as u8);
+    }
+
+    match c as u32 {
+        code @ (0x20..=0x7E | 0xA0..=0xFF) => Some(code as u8),
+        _ => None,
+    }
+}
+
+/// Encodes a string in WinAnsiEncoding, the encoding used for text shown with the standard
+/// fonts in generated appearance streams.  Characters the encoding lacks are replaced by `?`
+pub fn encode_win_ansi(text: &str) -> Vec<u8> {
+    text.chars()
+        .map(|c| win_ansi_byte(c).unwrap_or(b'?'))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_pdf_doc_encoding_low_codes() {
+        let bytes = (0x18..=0x1F).collect::<Vec<u8>>();
+        assert_eq!(decode_text_string(&bytes).unwrap(), "˘ˇˆ˙˝˛˚˜");
+        assert_eq!(decode_text_string(&[0x17, 0x20]).unwrap(), "\u{17} ");
+    }
+
+    #[test]
+    fn decodes_pdf_doc_encoding_high_codes() {
+        assert_eq!(decode_text_string(&[0x80]).unwrap(), "•");
+        assert_eq!(decode_text_string(&[0x84, 0x85]).unwrap(), "—–");
+        assert_eq!(decode_text_string(&[0x8D, 0x8E]).unwrap(), "“”");
+        assert_eq!(decode_text_string(&[0x93, 0x94]).unwrap(), "ﬁﬂ");
+        assert_eq!(decode_text_string(&[0x9F]).unwrap(), "\u{FFFD}");
+        assert_eq!(decode_text_string(&[0xA0]).unwrap(), "€");
+        assert_eq!(decode_text_string(&[0xA1, 0xFF]).unwrap(), "¡ÿ");
+        assert_eq!(decode_text_string(&[0x7F]).unwrap(), "\u{FFFD}");
+    }
+
+    #[test]
+    fn decodes_utf16_and_utf8() {
+        assert_eq!(
+            decode_text_string(&[0xFE, 0xFF, 0x03, 0x95, 0xD8, 0x3D, 0xDE, 0x00]).unwrap(),
+            "Ε😀"
+        );
+        assert_eq!(
+            decode_text_string(&[0xEF, 0xBB, 0xBF, 0xC3, 0xA9]).unwrap(),
+            "é"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_utf16() {
+        // Odd length
+        assert_eq!(decode_text_string(&[0xFE, 0xFF, 0x00, 0x41, 0x00]), None);
+        // Unpaired surrogate
+        assert_eq!(decode_text_string(&[0xFE, 0xFF, 0xD8, 0x3D]), None);
+        // Invalid UTF-8
+        assert_eq!(decode_text_string(&[0xEF, 0xBB, 0xBF, 0xC3]), None);
+    }
+
+    #[test]
+    fn encodes_in_pdf_doc_encoding_when_possible() {
+        assert_eq!(encode_text_string("Paris"), b"Paris");
+        assert_eq!(
+            encode_text_string("€ • ˘"),
+            vec![0xA0, 0x20, 0x80, 0x20, 0x18]
+        );
+        assert_eq!(encode_text_string(""), b"");
+    }
+
+    #[test]
+    fn encodes_in_utf16_otherwise() {
+        assert_eq!(
+            encode_text_string("Ω😀"),
+            vec![0xFE, 0xFF, 0x03, 0xA9, 0xD8, 0x3D, 0xDE, 0x00]
+        );
+        // The replacement character marks undefined codes, so it cannot be PDFDocEncoding
+        assert_eq!(encode_text_string("\u{FFFD}"), vec![0xFE, 0xFF, 0xFF, 0xFD]);
+        // Codes 0x7F and 0x9F are undefined in PDFDocEncoding
+        assert_eq!(encode_text_string("\u{7F}"), vec![0xFE, 0xFF, 0x00, 0x7F]);
+    }
+
+    #[test]
+    fn round_trips() {
+        for text in &[
+            "Grüße, Zoë",
+            "“quoted” — ﬁne…",
+            "Ελένη",
+            "日本語",
+            "a😀b",
+            "˘\u{17}",
+        ] {
+            assert_eq!(
+                decode_text_string(&encode_text_string(text)).as_deref(),
+                Some(*text)
+            );
+        }
+
+        // Every defined PDFDocEncoding code decodes to a character that encodes back to it
+        for byte in (0x00..=0xFF).filter(|&byte| byte != 0x7F && byte != 0x9F) {
+            let text = decode_text_string(&[byte]).unwrap();
+            assert_eq!(encode_text_string(&text), vec![byte], "code {:#04X}", byte);
+        }
+    }
+
+    #[test]
+    fn encodes_win_ansi_high_codes() {
+        assert_eq!(win_ansi_byte('€'), Some(0x80));
+        assert_eq!(win_ansi_byte('‚'), Some(0x82));
+        assert_eq!(win_ansi_byte('“'), Some(0x93));
+        assert_eq!(win_ansi_byte('—'), Some(0x97));
+        assert_eq!(win_ansi_byte('Ÿ'), Some(0x9F));
+        assert_eq!(win_ansi_byte('\u{A0}'), Some(0xA0));
+        assert_eq!(win_ansi_byte('ÿ'), Some(0xFF));
+        // Unused codes and control characters are not encoded
+        assert_eq!(win_ansi_byte('\u{81}'), None);
+        assert_eq!(win_ansi_byte('\u{FFFD}'), None);
+        assert_eq!(win_ansi_byte('\n'), None);
+        assert_eq!(win_ansi_byte('\u{7F}'), None);
+    }
+
+    #[test]
+    fn replaces_what_win_ansi_lacks() {
+        assert_eq!(encode_win_ansi("Ω 5€"), b"? 5\x80");
+    }
+}
@@ -0,0 +1,215 @@
+use crate::utils::get_field_flags;
+use crate::{FieldInfo, FieldState, FieldType, Form, ValueError, Widget};
+
+/// A handle to a single field of a `Form`
+///
+/// Handles are obtained with `Form::fields` or `Form::field` and read the field on demand, so they
+/// always reflect the current content of the form.
+#[derive(Clone, Copy)]
+pub struct Field<'a> {
+    form: &'a Form,
+    index: usize,
+}
+
+/// A handle to a single field of a `Form` that can also change the value of the field
+///
+/// Handles are obtained with `Form::fields_mut` or `Form::field_mut`.
+pub struct FieldMut<'a> {
+    form: &'a mut Form,
+    index: usize,
+}
+
+/// An iterator over the fields of a `Form`, created by `Form::fields`
+pub struct Fields<'a> {
+    form: &'a Form,
+    range: std::ops::Range<usize>,
+}
+
+/// A cursor over the fields of a `Form` that hands out mutable handles, created by
+/// `Form::fields_mut`
+///
+/// Every handle borrows the form mutably, so this can't be a regular `Iterator`.  Use it with
+/// `while let Some(mut field) = fields.next() { ... }` instead.
+pub struct FieldsMut<'a> {
+    form: &'a mut Form,
+    next: usize,
+}
+
+impl<'a> Field<'a> {
+    /// The index of the field in the form
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The partial name of the field
+    pub fn name(&self) -> Option<String> {
+        self.form.get_name(self.index)
+    }
+
+    /// The fully qualified name of the field, e.g. `applicant.address.city`
+    pub fn full_name(&self) -> Option<String> {
+        self.form.get_full_name(self.index)
+    }
+
+    /// The type of the field
+    pub fn field_type(&self) -> FieldType {
+        self.form.get_type(self.index)
+    }
+
+    /// The current state of the field
+    pub fn state(&self) -> FieldState {
+        self.form.get_state(self.index)
+    }
+
+    /// The metadata of the field, such as its tooltip or default value
+    pub fn info(&self) -> FieldInfo {
+        self.form.get_info(self.index)
+    }
+
+    /// The raw field flags (`/Ff`), including the ones inherited from its ancestors
+    pub fn flags(&self) -> u32 {
+        self.form
+            .get_field(self.index)
+            .map_or(0, |field| get_field_flags(&self.form.doc, field))
+    }
+
+    /// The widget annotations of the field
+    pub fn widgets(&self) -> Vec<Widget> {
+        self.form.get_widgets(self.index)
+    }
+}
+
+impl<'a> FieldMut<'a> {
+    fn as_field(&self) -> Field<'_> {
+        Field {
+            form: self.form,
+            index: self.index,
+        }
+    }
+
+    /// The index of the field in the form
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The partial name of the field
+    pub fn name(&self) -> Option<String> {
+        self.as_field().name()
+    }
+
+    /// The fully qualified name of the field, e.g. `applicant.address.city`
+    pub fn full_name(&self) -> Option<String> {
+        self.as_field().full_name()
+    }
+
+    /// The type of the field
+    pub fn field_type(&self) -> FieldType {
+        self.as_field().field_type()
+    }
+
+    /// The current state of the field
+    pub fn state(&self) -> FieldState {
+        self.as_field().state()
+    }
+
+    /// The metadata of the field, such as its tooltip or default value
+    pub fn info(&self) -> FieldInfo {
+        self.as_field().info()
+    }
+
+    /// The raw field flags (`/Ff`), including the ones inherited from its ancestors
+    pub fn flags(&self) -> u32 {
+        self.as_field().flags()
+    }
+
+    /// The widget annotations of the field
+    pub fn widgets(&self) -> Vec<Widget> {
+        self.as_field().widgets()
+    }
+
+    /// Same as `Form::set_text` for this field
+    pub fn set_text(&mut self, s: String) -> Result<(), ValueError> {
+        self.form.set_text(self.index, s)
+    }
+
+    /// Same as `Form::set_check_box` for this field
+    pub fn set_check_box(&mut self, is_checked: bool) -> Result<(), ValueError> {
+        self.form.set_check_box(self.index, is_checked)
+    }
+
+    /// Same as `Form::set_radio` for this field
+    pub fn set_radio(&mut self, choice: String) -> Result<(), ValueError> {
+        self.form.set_radio(self.index, choice)
+    }
+
+    /// Same as `Form::set_list_box` for this field
+    pub fn set_list_box(&mut self, choices: Vec<String>) -> Result<(), ValueError> {
+        self.form.set_list_box(self.index, choices)
+    }
+
+    /// Same as `Form::set_combo_box` for this field
+    pub fn set_combo_box(&mut self, choice: String) -> Result<(), ValueError> {
+        self.form.set_combo_box(self.index, choice)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Field<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let form = self.form;
+        self.range.next().map(|index| Field { form, index })
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.range.size_hint()
+    }
+}
+
+impl<'a> ExactSizeIterator for Fields<'a> {}
+
+impl<'a> FieldsMut<'a> {
+    /// Returns a handle to the next field, or `None` once all fields have been visited
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<FieldMut<'_>> {
+        if self.next < self.form.len() {
+            self.next += 1;
+            Some(FieldMut {
+                form: self.form,
+                index: self.next - 1,
+            })
+        } else {
+            None
+        }
+    }
+}
+
+impl Form {
+    /// Returns an iterator over handles to all of the fields in the form
+    pub fn fields(&self) -> Fields<'_> {
+        Fields {
+            form: self,
+            range: 0..self.len(),
+        }
+    }
+
+    /// Returns a handle to the field with the fully qualified name `name`, if there is one
+    pub fn field(&self, name: &str) -> Option<Field<'_>> {
+        let index = self.index_of(name)?;
+        Some(Field { form: self, index })
+    }
+
+    /// Returns a cursor over mutable handles to all of the fields in the form
+    pub fn fields_mut(&mut self) -> FieldsMut<'_> {
+        FieldsMut {
+            form: self,
+            next: 0,
+        }
+    }
+
+    /// Returns a mutable handle to the field with the fully qualified name `name`, if there is one
+    pub fn field_mut(&mut self, name: &str) -> Option<FieldMut<'_>> {
+        let index = self.index_of(name)?;
+        Some(FieldMut { form: self, index })
+    }
+}
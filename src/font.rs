@@ -0,0 +1,439 @@
+//! TrueType and OpenType fonts supplied by the user to generate appearances with
+//!
+//! The fonts are embedded as composite fonts with the `Identity-H` encoding, so the text of an
+//! appearance stream is written as two byte glyph ids and is not limited to the 256 characters of
+//! a simple font.  When the document is saved, every font is subsetted to the glyphs that were
+//! actually used and gets a ToUnicode CMap so the text can still be extracted.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::BTreeMap;
+use std::hash::{Hash, Hasher};
+
+use lopdf::{dictionary, Dictionary, Document, Object, ObjectId, Stream};
+use ttf_parser::{name_id, Face};
+
+use crate::{FontError, Form};
+
+/// The number of mappings per `beginbfchar` block, which the CMap format limits to 100
+const BF_CHAR_BLOCK: usize = 100;
+
+/// A font that is embedded into the document
+pub struct EmbeddedFont {
+    data: Vec<u8>,
+    /// The Type0 font dictionary, which appearance streams refer to.  It is only written when the
+    /// document is saved, as it depends on the glyphs used
+    pub id: ObjectId,
+    descendant_id: ObjectId,
+    descriptor_id: ObjectId,
+    font_file_id: ObjectId,
+    to_unicode_id: ObjectId,
+    /// The glyphs used so far, with the character each of them was used for
+    used: BTreeMap<u16, char>,
+}
+
+impl EmbeddedFont {
+    /// Checks that `data` is a font that can be embedded and reserves the objects for it in `doc`
+    pub fn new(doc: &mut Document, data: Vec<u8>) -> Result<Self, FontError> {
+        check(&data)?;
+
+        Ok(EmbeddedFont {
+            data,
+            id: doc.new_object_id(),
+            descendant_id: doc.new_object_id(),
+            descriptor_id: doc.new_object_id(),
+            font_file_id: doc.new_object_id(),
+            to_unicode_id: doc.new_object_id(),
+            used: BTreeMap::new(),
+        })
+    }
+
+    /// Checks that `data` is a font that can be embedded and lets it take over the objects of
+    /// this font, so the appearances that refer to this font get the new one
+    pub fn replace(&self, data: Vec<u8>) -> Result<Self, FontError> {
+        check(&data)?;
+
+        Ok(EmbeddedFont {
+            data,
+            id: self.id,
+            descendant_id: self.descendant_id,
+            descriptor_id: self.descriptor_id,
+            font_file_id: self.font_file_id,
+            to_unicode_id: self.to_unicode_id,
+            used: BTreeMap::new(),
+        })
+    }
+
+    fn face(&self) -> Face<'_> {
+        Face::from_slice(&self.data, 0).expect("the font was checked when it was embedded")
+    }
+
+    /// Encodes `text` as glyph ids, the way the font expects it in a `Tj` operator, and records
+    /// the glyphs as used.  Characters the font lacks are shown as its `.notdef` glyph
+    pub fn encode(&mut self, text: &str) -> Vec<u8> {
+        // Not `self.face()`, as that would borrow all of `self`
+        let face =
+            Face::from_slice(&self.data, 0).expect("the font was checked when it was embedded");
+
+        let mut bytes = Vec::with_capacity(text.len() * 2);
+        for c in text.chars() {
+            let gid = face.glyph_index(c).map_or(0, |gid| gid.0);
+            self.used.entry(gid).or_insert(c);
+            bytes.extend_from_slice(&gid.to_be_bytes());
+        }
+        bytes
+    }
+
+    /// The width of the glyph that shows `c`, in thousandths of the font size
+    pub fn width(&self, c: char) -> f32 {
+        let face = self.face();
+        let gid = face.glyph_index(c).unwrap_or(ttf_parser::GlyphId(0));
+        face.glyph_hor_advance(gid).map_or(0.0, f32::from) * 1000.0 / f32::from(face.units_per_em())
+    }
+
+    /// Removes the font and all of its parts from `doc`, should an earlier save have written them
+    pub fn remove(&self, doc: &mut Document) {
+        for id in [
+            self.id,
+            self.descendant_id,
+            self.descriptor_id,
+            self.font_file_id,
+            self.to_unicode_id,
+        ] {
+            doc.objects.remove(&id);
+        }
+    }
+
+    /// Writes the font and all of its parts to `doc`, replacing what an earlier save wrote
+    pub fn write(&self, doc: &mut Document) {
+        let face = self.face();
+        let scale = 1000.0 / f32::from(face.units_per_em());
+        let is_cff = face.tables().cff.is_some();
+
+        let name = face
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == name_id::POST_SCRIPT_NAME)
+            .find_map(|name| name.to_string())
+            .unwrap_or_else(|| "Font".to_owned());
+        let base_font = format!("{}+{}", self.subset_tag(), name);
+
+        // The subset keeps the glyph ids, so no remapping is needed.  The .notdef glyph is
+        // always kept
+        let mut glyphs = vec![0];
+        glyphs.extend(self.used.keys().filter(|&&gid| gid != 0));
+        let subset = subsetter::subset(&self.data, 0, subsetter::Profile::pdf(&glyphs))
+            .unwrap_or_else(|_| self.data.clone());
+
+        let mut font_file = if is_cff {
+            Stream::new(dictionary! { "Subtype" => "OpenType" }, subset)
+        } else {
+            Stream::new(dictionary! { "Length1" => subset.len() as i64 }, subset)
+        };
+        let _ = font_file.compress();
+        doc.objects
+            .insert(self.font_file_id, Object::Stream(font_file));
+
+        let bbox = face.global_bounding_box();
+        let descriptor = dictionary! {
+            "Type" => "FontDescriptor",
+            "FontName" => Object::Name(base_font.clone().into_bytes()),
+            // Symbolic, as the glyphs are not addressed by a standard character set
+            "Flags" => 4,
+            "FontBBox" => vec![
+                (f32::from(bbox.x_min) * scale).into(),
+                (f32::from(bbox.y_min) * scale).into(),
+                (f32::from(bbox.x_max) * scale).into(),
+                (f32::from(bbox.y_max) * scale).into(),
+            ],
+            "ItalicAngle" => face.italic_angle().unwrap_or(0.0),
+            "Ascent" => f32::from(face.ascender()) * scale,
+            "Descent" => f32::from(face.descender()) * scale,
+            "CapHeight" => f32::from(face.capital_height().unwrap_or_else(|| face.ascender())) * scale,
+            "StemV" => 80,
+            if is_cff { "FontFile3" } else { "FontFile2" } => self.font_file_id,
+        };
+        doc.objects
+            .insert(self.descriptor_id, Object::Dictionary(descriptor));
+
+        let mut widths = Vec::new();
+        for &gid in self.used.keys() {
+            let width = face
+                .glyph_hor_advance(ttf_parser::GlyphId(gid))
+                .map_or(0.0, |advance| f32::from(advance) * scale);
+            widths.push(Object::Integer(i64::from(gid)));
+            widths.push(Object::Array(vec![width.round().into()]));
+        }
+
+        let mut descendant = dictionary! {
+            "Type" => "Font",
+            "Subtype" => if is_cff { "CIDFontType0" } else { "CIDFontType2" },
+            "BaseFont" => Object::Name(base_font.clone().into_bytes()),
+            "CIDSystemInfo" => dictionary! {
+                "Registry" => Object::string_literal("Adobe"),
+                "Ordering" => Object::string_literal("Identity"),
+                "Supplement" => 0,
+            },
+            "FontDescriptor" => self.descriptor_id,
+            "W" => widths,
+        };
+        if !is_cff {
+            descendant.set("CIDToGIDMap", "Identity");
+        }
+        doc.objects
+            .insert(self.descendant_id, Object::Dictionary(descendant));
+
+        let mut to_unicode = Stream::new(Dictionary::new(), self.to_unicode_cmap().into_bytes());
+        let _ = to_unicode.compress();
+        doc.objects
+            .insert(self.to_unicode_id, Object::Stream(to_unicode));
+
+        doc.objects.insert(
+            self.id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Font",
+                "Subtype" => "Type0",
+                "BaseFont" => Object::Name(base_font.into_bytes()),
+                "Encoding" => "Identity-H",
+                "DescendantFonts" => vec![self.descendant_id.into()],
+                "ToUnicode" => self.to_unicode_id,
+            }),
+        );
+    }
+
+    /// The six uppercase letters that prefix the name of a font subset, derived from the glyphs in
+    /// the subset
+    fn subset_tag(&self) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.used.hash(&mut hasher);
+        let mut hash = hasher.finish();
+
+        (0..6)
+            .map(|_| {
+                let c = (b'A' + (hash % 26) as u8) as char;
+                hash /= 26;
+                c
+            })
+            .collect()
+    }
+
+    /// Builds the CMap that maps the glyph ids back to the characters they were used for
+    fn to_unicode_cmap(&self) -> String {
+        let mut cmap = String::from(
+            "/CIDInit /ProcSet findresource begin\n\
+             12 dict begin\n\
+             begincmap\n\
+             /CIDSystemInfo << /Registry (Adobe) /Ordering (UCS) /Supplement 0 >> def\n\
+             /CMapName /Adobe-Identity-UCS def\n\
+             /CMapType 2 def\n\
+             1 begincodespacerange\n\
+             <0000> <FFFF>\n\
+             endcodespacerange\n",
+        );
+
+        let mappings = self
+            .used
+            .iter()
+            .filter(|(&gid, _)| gid != 0)
+            .collect::<Vec<_>>();
+        for block in mappings.chunks(BF_CHAR_BLOCK) {
+            cmap.push_str(&format!("{} beginbfchar\n", block.len()));
+            for (gid, c) in block {
+                let mut units = [0; 2];
+                let utf16 = c
+                    .encode_utf16(&mut units)
+                    .iter()
+                    .map(|unit| format!("{:04X}", unit))
+                    .collect::<String>();
+                cmap.push_str(&format!("<{:04X}> <{}>\n", gid, utf16));
+            }
+            cmap.push_str("endbfchar\n");
+        }
+
+        cmap.push_str(
+            "endcmap\n\
+             CMapName currentdict /CMap defineresource pop\n\
+             end\n\
+             end\n",
+        );
+        cmap
+    }
+}
+
+/// Checks that `data` is a TrueType or OpenType font with outlines that can be embedded
+fn check(data: &[u8]) -> Result<(), FontError> {
+    let face = Face::from_slice(data, 0).map_err(|_| FontError::InvalidFont)?;
+    if face.tables().glyf.is_none() && face.tables().cff.is_none() {
+        return Err(FontError::InvalidFont);
+    }
+    Ok(())
+}
+
+impl Form {
+    /// Embeds the TrueType or OpenType font `data` into the document, so it can be used for the
+    /// generated appearances of text fields.  The font is referred to by `name`, which is also the
+    /// name of its resource in the appearance streams, e.g. `NotoSans`.  Registering a font under
+    /// a name that is already taken replaces the previous font, and the appearances generated
+    /// with it are generated again
+    ///
+    /// Only the glyphs that are used by the appearances are embedded when the form is saved
+    pub fn embed_font(&mut self, name: &str, data: Vec<u8>) -> Result<(), FontError> {
+        // The appearances generated with a replaced font refer to its objects, so the new font
+        // takes them over
+        let font = match self.fonts.get(name) {
+            Some(old) => old.replace(data)?,
+            None => EmbeddedFont::new(&mut self.doc, data)?,
+        };
+        let id = font.id;
+
+        if self.fonts.insert(name.to_owned(), font).is_some() {
+            for n in 0..self.len() {
+                if self.shows_font(n, id) {
+                    self.update_appearance(n);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Uses the embedded font `name` for the appearances of all text fields that have no font
+    /// set with `set_field_font`.  With `None`, the fonts of the default appearances (`/DA`) of
+    /// the fields are used again
+    pub fn set_default_font(&mut self, name: Option<&str>) -> Result<(), FontError> {
+        self.default_font = self.check_font(name)?;
+        Ok(())
+    }
+
+    /// Uses the embedded font `name` for the appearance of the field at index `n`.  With `None`,
+    /// the field falls back to the font set with `set_default_font`.  If there is no field at
+    /// index `n`, returns FontError
+    pub fn set_field_font(&mut self, n: usize, name: Option<&str>) -> Result<(), FontError> {
+        let oid = *self.form_ids.get(n).ok_or(FontError::NoSuchField)?;
+        match self.check_font(name)? {
+            Some(name) => self.field_fonts.insert(oid, name),
+            None => self.field_fonts.remove(&oid),
+        };
+        Ok(())
+    }
+
+    /// Same as `set_field_font`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns FontError
+    pub fn set_field_font_by_name(
+        &mut self,
+        field_name: &str,
+        name: Option<&str>,
+    ) -> Result<(), FontError> {
+        let n = self.index_of(field_name).ok_or(FontError::NoSuchField)?;
+        self.set_field_font(n, name)
+    }
+
+    /// Makes sure the font `name` has been embedded
+    fn check_font(&self, name: Option<&str>) -> Result<Option<String>, FontError> {
+        match name {
+            Some(name) if !self.fonts.contains_key(name) => Err(FontError::NoSuchFont),
+            name => Ok(name.map(str::to_owned)),
+        }
+    }
+
+    /// The name of the embedded font used for the appearance of the field at index `n`, if any
+    pub(crate) fn field_font(&self, n: usize) -> Option<&str> {
+        self.field_fonts
+            .get(&self.form_ids[n])
+            .or(self.default_font.as_ref())
+            .map(String::as_str)
+    }
+
+    /// Whether an appearance of the field at index `n` refers to the font object `font_id`
+    fn shows_font(&self, n: usize, font_id: ObjectId) -> bool {
+        fn deref_dict<'a>(doc: &'a Document, object: Option<&'a Object>) -> Option<&'a Dictionary> {
+            doc.dereference(object?).ok()?.1.as_dict().ok()
+        }
+
+        let doc = &self.doc;
+        self.widget_ids[n].iter().any(|&oid| {
+            let widget = doc.get_dictionary(oid).ok();
+            let appearance = deref_dict(doc, widget.and_then(|widget| widget.get(b"AP").ok()));
+            let stream = appearance
+                .and_then(|appearance| appearance.get(b"N").ok())
+                .and_then(|n| doc.dereference(n).ok())
+                .and_then(|(_, n)| n.as_stream().ok());
+            let resources = deref_dict(
+                doc,
+                stream.and_then(|stream| stream.dict.get(b"Resources").ok()),
+            );
+            let fonts = deref_dict(
+                doc,
+                resources.and_then(|resources| resources.get(b"Font").ok()),
+            );
+
+            fonts.is_some_and(|fonts| {
+                fonts
+                    .iter()
+                    .any(|(_, font)| matches!(*font, Object::Reference(id) if id == font_id))
+            })
+        })
+    }
+
+    /// Writes the embedded fonts that the appearances refer to, subsetted to the glyphs used so
+    /// far.  The other fonts are left out, and removed if an earlier save wrote them
+    pub(crate) fn write_fonts(&mut self) {
+        for font in self.fonts.values() {
+            if (0..self.len()).any(|n| self.shows_font(n, font.id)) {
+                font.write(&mut self.doc);
+            } else {
+                font.remove(&mut self.doc);
+            }
+        }
+    }
+}
+
+/// Adds the font `font`, usually a reference, to the resources of an appearance stream under the
+/// name `font_name`.  The resources are copied rather than changed in place, as other appearances
+/// may share them
+pub fn font_resources(
+    doc: &Document,
+    stream: &Stream,
+    font_name: &str,
+    font: Object,
+) -> Dictionary {
+    let deref_dict = |object: Option<&Object>| -> Dictionary {
+        object
+            .and_then(|object| doc.dereference(object).ok())
+            .and_then(|(_, object)| object.as_dict().ok())
+            .cloned()
+            .unwrap_or_default()
+    };
+
+    let mut resources = deref_dict(stream.dict.get(b"Resources").ok());
+    let mut fonts = deref_dict(resources.get(b"Font").ok());
+    fonts.set(font_name, font);
+    resources.set("Font", fonts);
+    resources
+}
+
+/// Removes the fonts `font_ids`, given as object ids, from the resources of an appearance stream,
+/// so a font the appearance no longer shows is not written for its sake.  Returns the updated
+/// resources if they had to change.  The resources are copied rather than changed in place, as
+/// other appearances may share them
+pub fn without_fonts(doc: &Document, stream: &Stream, font_ids: &[ObjectId]) -> Option<Dictionary> {
+    let deref_dict = |object: &Object| -> Option<Dictionary> {
+        doc.dereference(object).ok()?.1.as_dict().ok().cloned()
+    };
+
+    let mut resources = deref_dict(stream.dict.get(b"Resources").ok()?)?;
+    let mut fonts = deref_dict(resources.get(b"Font").ok()?)?;
+
+    let names = fonts
+        .iter()
+        .filter(|(_, font)| matches!(**font, Object::Reference(id) if font_ids.contains(&id)))
+        .map(|(name, _)| name.clone())
+        .collect::<Vec<_>>();
+    if names.is_empty() {
+        return None;
+    }
+
+    for name in names {
+        fonts.remove(&name);
+    }
+    resources.set("Font", fonts);
+    Some(resources)
+}
@@ -0,0 +1,209 @@
+//! Arrangement of the text of generated appearances
+
+use crate::metrics::Metrics;
+use crate::Alignment;
+
+/// The distance between the baselines of two lines of text, relative to the font size
+pub const LEADING: f32 = 1.15;
+
+/// Breaks `text` into lines that fit into `width` when shown at the font size `size`.  Lines are
+/// broken at explicit line breaks and between words.  Words that are too wide on their own are
+/// broken between characters
+pub fn wrap(text: &str, metrics: &Metrics, size: f32, width: f32) -> Vec<String> {
+    let fits = |line: &str| metrics.text_width(line, size) <= width;
+
+    let mut lines = Vec::new();
+    for paragraph in text.split("\r\n").flat_map(|s| s.split(&['\r', '\n'][..])) {
+        let mut line = String::new();
+        for (i, word) in paragraph.split(' ').enumerate() {
+            if i > 0 {
+                let candidate = format!("{} {}", line, word);
+                if fits(&candidate) {
+                    line = candidate;
+                    continue;
+                }
+                lines.push(line);
+            }
+            line = word.to_owned();
+
+            // A word that is wider than the field on its own gets lines of its own
+            while !fits(&line) && line.chars().nth(1).is_some() {
+                let mut end = line.char_indices().nth(1).map_or(line.len(), |(i, _)| i);
+                for (i, c) in line.char_indices().skip(1) {
+                    if !fits(&line[..i + c.len_utf8()]) {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                }
+                let rest = line.split_off(end);
+                lines.push(std::mem::replace(&mut line, rest));
+            }
+        }
+        lines.push(line);
+    }
+
+    lines
+}
+
+/// The smallest font size automatic sizing picks, even if the text does not fit
+pub const MIN_AUTO_FONT_SIZE: f32 = 4.0;
+
+/// The largest font size automatic sizing picks, even if the field has room for larger text
+pub const MAX_AUTO_FONT_SIZE: f32 = 12.0;
+
+/// How much automatic sizing shrinks the font of multiline text in every step
+const AUTO_FONT_SIZE_STEP: f32 = 0.5;
+
+/// Picks the font size for a default appearance with the size 0, which means the text is sized
+/// automatically.  A single line gets the largest size that fits into the `width` and `height` of
+/// the field, multiline text the largest size whose wrapped lines fit
+pub fn auto_font_size(
+    text: &str,
+    metrics: &Metrics,
+    width: f32,
+    height: f32,
+    multiline: bool,
+) -> f32 {
+    if multiline {
+        let mut size = MAX_AUTO_FONT_SIZE;
+        while size > MIN_AUTO_FONT_SIZE {
+            let lines = wrap(text, metrics, size, width).len() as f32;
+            if (lines - 1.0) * LEADING * size + size <= height {
+                break;
+            }
+            size -= AUTO_FONT_SIZE_STEP;
+        }
+        size.max(MIN_AUTO_FONT_SIZE)
+    } else {
+        let text_width = metrics.text_width(text, 1.0);
+        let mut size = height / LEADING;
+        if text_width > 0.0 {
+            size = size.min(width / text_width);
+        }
+        size.clamp(MIN_AUTO_FONT_SIZE, MAX_AUTO_FONT_SIZE)
+    }
+}
+
+/// The horizontal position of a line of text that is `line_width` wide in a box that is
+/// `box_width` wide, keeping `padding` away from the edges of the box
+pub fn align(line_width: f32, box_width: f32, padding: f32, alignment: Alignment) -> f32 {
+    match alignment {
+        Alignment::Left => padding,
+        Alignment::Center => 0.5 * (box_width - line_width),
+        Alignment::Right => box_width - padding - line_width,
+    }
+}
+
+/// Places every character of `text` centered in one of `max_len` cells that split a box
+/// `box_width` wide, as in comb fields.  Returns the characters with their horizontal positions.
+/// Text shorter than the number of cells fills the cells according to `alignment`
+pub fn comb(
+    text: &str,
+    metrics: &Metrics,
+    size: f32,
+    box_width: f32,
+    max_len: usize,
+    alignment: Alignment,
+) -> Vec<(String, f32)> {
+    let cell_width = box_width / max_len as f32;
+    let empty = max_len.saturating_sub(text.chars().count());
+    let first = match alignment {
+        Alignment::Left => 0,
+        Alignment::Center => empty / 2,
+        Alignment::Right => empty,
+    };
+
+    text.chars()
+        .take(max_len)
+        .enumerate()
+        .map(|(i, c)| {
+            let char_width = metrics.char_width(c) * size / 1000.0;
+            let x = (first + i) as f32 * cell_width + 0.5 * (cell_width - char_width);
+            (c.to_string(), x)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::COURIER;
+
+    /// Every character of Courier is 600 thousandths wide, 6pt at the size 10
+    const MONO: Metrics<'static> = Metrics::WinAnsi(&COURIER);
+
+    #[test]
+    fn wraps_at_explicit_line_breaks() {
+        assert_eq!(
+            wrap("a\r\nb\rc\n\nd", &MONO, 10.0, 100.0),
+            vec!["a", "b", "c", "", "d"]
+        );
+        assert_eq!(wrap("", &MONO, 10.0, 100.0), vec![""]);
+    }
+
+    #[test]
+    fn wraps_between_words() {
+        assert_eq!(
+            wrap("aaa bbb ccc", &MONO, 10.0, 42.0),
+            vec!["aaa bbb", "ccc"]
+        );
+        assert_eq!(
+            wrap("aaa bbb ccc", &MONO, 10.0, 41.9),
+            vec!["aaa", "bbb", "ccc"]
+        );
+        assert_eq!(
+            wrap("one two\nthree four", &MONO, 10.0, 60.0),
+            vec!["one two", "three four"]
+        );
+    }
+
+    #[test]
+    fn breaks_words_too_wide_for_the_field() {
+        assert_eq!(
+            wrap("abcdefghij", &MONO, 10.0, 24.0),
+            vec!["abcd", "efgh", "ij"]
+        );
+        assert_eq!(
+            wrap("ab abcdefgh cd", &MONO, 10.0, 24.0),
+            vec!["ab", "abcd", "efgh", "cd"]
+        );
+        // Every line keeps at least one character, even if it does not fit
+        assert_eq!(wrap("abc", &MONO, 10.0, 1.0), vec!["a", "b", "c"]);
+        assert_eq!(wrap("äöü", &MONO, 10.0, 12.0), vec!["äö", "ü"]);
+    }
+
+    #[test]
+    fn sizes_single_lines_to_fit() {
+        // Limited by the width
+        assert_eq!(auto_font_size("aaaaa", &MONO, 30.0, 100.0, false), 10.0);
+        // Limited by the height
+        assert_eq!(auto_font_size("a", &MONO, 1000.0, 11.5, false), 10.0);
+        // Empty text only depends on the height
+        assert_eq!(auto_font_size("", &MONO, 1.0, 9.2, false), 8.0);
+    }
+
+    #[test]
+    fn clamps_automatic_sizes() {
+        assert_eq!(
+            auto_font_size("a", &MONO, 1000.0, 1000.0, false),
+            MAX_AUTO_FONT_SIZE
+        );
+        assert_eq!(
+            auto_font_size(&"a".repeat(100), &MONO, 10.0, 100.0, false),
+            MIN_AUTO_FONT_SIZE
+        );
+        assert_eq!(
+            auto_font_size(&"a ".repeat(500), &MONO, 10.0, 10.0, true),
+            MIN_AUTO_FONT_SIZE
+        );
+    }
+
+    #[test]
+    fn sizes_multiline_text_so_the_lines_fit() {
+        let text = "aaa aaa aaa aaa";
+        assert_eq!(auto_font_size(text, &MONO, 60.0, 100.0, true), 12.0);
+        // Two lines at 9pt take 9 * 1.15 + 9 = 19.35pt, at 9.5pt the first line holds only
+        // two words and three lines do not fit
+        assert_eq!(auto_font_size(text, &MONO, 60.0, 20.0, true), 9.0);
+    }
+}
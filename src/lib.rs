@@ -3,9 +3,16 @@ extern crate bitflags;
 #[macro_use]
 extern crate derive_error;
 
+mod appearance;
+mod default_appearance;
+mod encoding;
+mod field;
+mod font;
+mod layout;
+mod metrics;
 mod utils;
 
-use std::collections::VecDeque;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::io;
 use std::io::Write;
 use std::path::Path;
@@ -14,18 +21,44 @@ use std::str;
 use bitflags::_core::str::from_utf8;
 
 use lopdf::content::{Content, Operation};
-use lopdf::{Document, Object, ObjectId, StringFormat};
+use lopdf::{Dictionary, Document, Object, ObjectId, StringFormat};
 
+use crate::appearance::{
+    button_appearances, fill_color, normal_appearance, rectangle, Contents, Frame, HIGHLIGHT,
+};
+pub use crate::default_appearance::{ColorSpace, DefaultAppearance};
+use crate::encoding::{decode_text_string, encode_text_string, encode_win_ansi};
+pub use crate::field::{Field, FieldMut, Fields, FieldsMut};
+use crate::font::{font_resources, without_fonts, EmbeddedFont};
+use crate::layout::{align, auto_font_size, wrap, LEADING, MAX_AUTO_FONT_SIZE};
+use crate::metrics::{Metrics, HELVETICA};
+pub use crate::utils::AnnotationFlags;
 use crate::utils::*;
 
 /// A PDF Form that contains fillable fields
 ///
 /// Use this struct to load an existing PDF with a fillable form using the `load` method.  It will
 /// analyze the PDF and identify the fields. Then you can get and set the content of the fields by
-/// index.
+/// index, or look up the index of a field by its fully qualified name with `index_of`.
 pub struct Form {
     doc: Document,
     form_ids: Vec<ObjectId>,
+    /// The widget annotations of each field in `form_ids`
+    widget_ids: Vec<Vec<ObjectId>>,
+    /// Maps the fully qualified name of every named field to its dictionary
+    names: HashMap<String, ObjectId>,
+    /// Maps pages and the annotations placed on them to the index of the page
+    pages: HashMap<ObjectId, usize>,
+    /// The fonts embedded with `embed_font`, by name
+    fonts: HashMap<String, EmbeddedFont>,
+    /// The embedded font used for the fields without a font of their own
+    default_font: Option<String>,
+    /// The embedded fonts chosen for single fields
+    field_fonts: HashMap<ObjectId, String>,
+    /// How the appearances of filled in fields are produced
+    fill_mode: FillMode,
+    /// The fields whose appearances were left to the viewer and are outdated
+    stale_appearances: BTreeSet<usize>,
 }
 
 /// The possible types of fillable form fields in a PDF
@@ -37,6 +70,7 @@ pub enum FieldType {
     ListBox,
     ComboBox,
     Text,
+    Signature,
     Unknown,
 }
 
@@ -50,6 +84,8 @@ pub enum LoadError {
     NoSuchReference(ObjectId),
     /// An element that was expected to be a reference was not a reference
     NotAReference,
+    /// The document does not contain a form
+    NoForm,
 }
 
 /// Errors That may occur while setting values in a form
@@ -63,7 +99,92 @@ pub enum ValueError {
     TooManySelected,
     /// Readonly field cannot be edited
     Readonly,
+    /// No field with the given name exists in the form
+    NoSuchField,
+    /// The text has more characters than the cells of the comb field (`/MaxLen`)
+    TooLong,
 }
+
+/// Errors that may occur while embedding fonts or choosing them for fields
+#[derive(Debug, Error)]
+pub enum FontError {
+    /// The data is not a TrueType or OpenType font that can be embedded
+    InvalidFont,
+    /// No font has been embedded under the given name
+    NoSuchFont,
+    /// No field with the given index or name exists in the form
+    NoSuchField,
+}
+
+/// A widget annotation, the visual representation of a form field on a page.  A field may have
+/// several widgets, e.g. when the same value is shown on multiple pages
+#[derive(Debug, Clone)]
+pub struct Widget {
+    /// The index of the page the widget is placed on, if it could be determined
+    pub page: Option<usize>,
+    /// The rectangle of the widget on its page, as `[llx, lly, urx, ury]`
+    pub rect: [f32; 4],
+    /// The appearance dictionary (`/AP`) of the widget, if it has one
+    pub appearance: Option<Dictionary>,
+    /// The annotation flags (`/F`) of the widget
+    pub flags: AnnotationFlags,
+}
+
+/// How the appearances of text fields and choice fields are produced when they are filled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FillMode {
+    /// Appearances are generated, and the `/NeedAppearances` flag of the form is left as it is
+    #[default]
+    Generate,
+    /// Appearances are generated and the `/NeedAppearances` flag of the form is cleared, so the
+    /// form looks the same in every viewer
+    GenerateAndClearNeedAppearances,
+    /// No appearances are generated.  The `/NeedAppearances` flag of the form is set instead, so
+    /// the viewer renders the values itself
+    NeedAppearances,
+}
+
+/// The alignment of the text of a field, its quadding (`/Q`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Alignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Metadata of a form field that is not part of its state
+#[derive(Debug, Clone, Default)]
+pub struct FieldInfo {
+    /// The alternate name (`/TU`) of the field, meant to be shown as tooltip or label
+    pub alternate_name: Option<String>,
+    /// The mapping name (`/TM`) of the field, used when exporting the form data
+    pub mapping_name: Option<String>,
+    /// The maximum length (`/MaxLen`) of the text of a text field
+    pub max_len: Option<u32>,
+    /// The alignment of the text of the field
+    pub alignment: Alignment,
+    /// The font and text color of the field (`/DA`), which may be inherited from the form
+    pub default_appearance: Option<DefaultAppearance>,
+    /// The value (`/DV`) the field is reset to.  Single valued fields have at most one entry
+    pub default_value: Vec<String>,
+    /// The raw field flags (`/Ff`), including the ones inherited from its ancestors
+    pub flags: u32,
+}
+
+/// Errors that may occur while reading the fields of a form
+#[derive(Debug, Error)]
+pub enum FieldError {
+    /// An Lopdf Error
+    LopdfError(lopdf::Error),
+    /// The index is greater than the number of fields
+    #[error(non_std, no_from)]
+    IndexOutOfRange(usize),
+    /// The field does not conform to the PDF specification
+    #[error(msg_embedded, non_std, no_from)]
+    Malformed(String),
+}
+
 /// The current state of a form field
 #[derive(Debug)]
 pub enum FieldState {
@@ -101,6 +222,35 @@ pub enum FieldState {
     /// User Text Input
     Text {
         text: String,
+        /// The text may span multiple lines
+        multiline: bool,
+        /// The text is a password that should not be echoed
+        password: bool,
+        /// The text is the path of a file whose contents are submitted
+        file_select: bool,
+        /// The text is split into `/MaxLen` equally spaced cells
+        comb: bool,
+        /// The text may not scroll beyond the bounds of the field
+        do_not_scroll: bool,
+        /// The value is also available as rich text (`/RV`)
+        rich_text: bool,
+        readonly: bool,
+        required: bool,
+    },
+    /// A signature field.  The details are taken from the signature dictionary (`/V`) and are
+    /// `None` when the field is not signed or the signer did not provide them
+    Signature {
+        signed: bool,
+        /// The name of the person or authority signing (`/Name`)
+        signer: Option<String>,
+        /// The time of signing (`/M`) as PDF date string, e.g. `D:20200101120000Z`
+        signing_time: Option<String>,
+        /// The reason for signing (`/Reason`)
+        reason: Option<String>,
+        /// The location of signing (`/Location`)
+        location: Option<String>,
+        /// The encoding of the signature (`/SubFilter`), e.g. `adbe.pkcs7.detached`
+        sub_filter: Option<String>,
         readonly: bool,
         required: bool,
     },
@@ -138,44 +288,115 @@ impl Form {
 
     fn load_doc(mut doc: Document) -> Result<Self, LoadError> {
         let mut form_ids = Vec::new();
+        let mut widget_ids = Vec::new();
         let mut queue = VecDeque::new();
+        let mut visited = HashSet::new();
         // Block so borrow of doc ends before doc is moved into the result
         {
             doc.decompress();
 
-            let acroform = doc
-                .objects
-                .get_mut(
-                    &doc.trailer
-                        .get(b"Root")?
-                        .deref(&doc)?
-                        .as_dict()?
-                        .get(b"AcroForm")?
-                        .as_reference()?,
-                )
-                .ok_or(LoadError::NotAReference)?
-                .as_dict_mut()?;
-
-            let fields_list = acroform.get(b"Fields")?.as_array()?;
-            queue.append(&mut VecDeque::from(fields_list.clone()));
+            let catalog = doc.trailer.get(b"Root")?.deref(&doc)?.as_dict()?;
+
+            // The AcroForm may be written inline in the catalog or as an indirect object
+            let acroform = match catalog.get(b"AcroForm") {
+                Ok(acroform) => doc.dereference(acroform)?.1.as_dict()?,
+                Err(_) => return Err(LoadError::NoForm),
+            };
+
+            // A form without a list of fields simply has no fields
+            if let Ok(fields_list) = acroform.get(b"Fields") {
+                let fields_list = doc.dereference(fields_list)?.1.as_array()?;
+                queue.append(&mut VecDeque::from(fields_list.clone()));
+            }
 
             // Iterate over the fields
             while let Some(objref) = queue.pop_front() {
-                let obj = objref.deref(&doc)?;
-                if let Object::Dictionary(ref dict) = *obj {
-                    // If the field has FT, it actually takes input.  Save this
-                    if dict.get(b"FT").is_ok() {
-                        form_ids.push(objref.as_reference().unwrap());
+                // Fields written inline cannot be referred to, so they are skipped, as are fields
+                // that do not exist.  A field that was already visited means the kids form a cycle
+                let oid = match objref.as_reference() {
+                    Ok(oid) => oid,
+                    Err(_) => continue,
+                };
+                if !visited.insert(oid) {
+                    continue;
+                }
+
+                if let Ok(Object::Dictionary(ref dict)) = doc.get_object(oid) {
+                    // Kids with a partial name are fields of their own, kids without one are
+                    // only widget annotations of this field
+                    let mut has_child_fields = false;
+                    let mut widgets = Vec::new();
+                    if let Ok(Object::Array(kids)) = dict.get(b"Kids") {
+                        for kid in kids {
+                            if let Ok(Object::Dictionary(ref kid_dict)) = kid.deref(&doc) {
+                                if kid_dict.has(b"T") {
+                                    queue.push_back(kid.clone());
+                                    has_child_fields = true;
+                                } else if let Ok(kid) = kid.as_reference() {
+                                    widgets.push(kid);
+                                }
+                            }
+                        }
                     }
 
-                    // If this field has kids, they might have FT, so add them to the queue
-                    if let Ok(&Object::Array(ref kids)) = dict.get(b"Kids") {
-                        queue.append(&mut VecDeque::from(kids.clone()));
+                    // If the terminal field has FT, possibly inherited from an ancestor, it
+                    // actually takes input.  Save this
+                    if !has_child_fields && get_inheritable(&doc, dict, b"FT").is_ok() {
+                        // A field without widget kids is merged with its only widget
+                        if widgets.is_empty() && is_widget(dict) {
+                            widgets.push(oid);
+                        }
+
+                        form_ids.push(oid);
+                        widget_ids.push(widgets);
                     }
                 }
             }
         }
-        Ok(Form { doc, form_ids })
+
+        // Index the fields by their fully qualified names. Should several fields share a name,
+        // the first one in traversal order wins
+        let mut names = HashMap::new();
+        for &oid in &form_ids {
+            if let Some(name) = doc
+                .get_dictionary(oid)
+                .ok()
+                .and_then(|field| get_full_name(&doc, field))
+            {
+                names.entry(name).or_insert(oid);
+            }
+        }
+
+        // Remember on which page each annotation is placed
+        let mut pages = HashMap::new();
+        for (i, (_, page_id)) in doc.get_pages().into_iter().enumerate() {
+            pages.insert(page_id, i);
+            if let Ok(page) = doc.get_dictionary(page_id) {
+                if let Ok((_, Object::Array(annots))) = page
+                    .get(b"Annots")
+                    .and_then(|annots| doc.dereference(annots))
+                {
+                    for annot in annots {
+                        if let Ok(annot_id) = annot.as_reference() {
+                            pages.insert(annot_id, i);
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(Form {
+            doc,
+            form_ids,
+            widget_ids,
+            names,
+            pages,
+            fonts: HashMap::new(),
+            default_font: None,
+            field_fonts: HashMap::new(),
+            fill_mode: FillMode::default(),
+            stale_appearances: BTreeSet::new(),
+        })
     }
 
     /// Returns the number of fields the form has
@@ -188,23 +409,29 @@ impl Form {
         self.len() == 0
     }
 
-    /// Gets the type of field of the given index
+    /// Gets the type of field of the given index.  Fields with a malformed type are reported as
+    /// `FieldType::Unknown`
     ///
     /// # Panics
     /// This function will panic if the index is greater than the number of fields
     pub fn get_type(&self, n: usize) -> FieldType {
-        // unwraps should be fine because load should have verified everything exists
-        let field = self
-            .doc
-            .objects
-            .get(&self.form_ids[n])
-            .unwrap()
-            .as_dict()
-            .unwrap();
-
-        let type_str = field.get(b"FT").unwrap().as_name_str().unwrap();
-        if type_str == "Btn" {
-            let flags = ButtonFlags::from_bits_truncate(get_field_flags(field));
+        match self.try_get_type(n) {
+            Ok(field_type) => field_type,
+            Err(FieldError::IndexOutOfRange(n)) => panic!("field index {} is out of range", n),
+            Err(_) => FieldType::Unknown,
+        }
+    }
+
+    /// Gets the type of field of the given index.
+    /// If the index is out of range or the field is malformed, returns FieldError
+    pub fn try_get_type(&self, n: usize) -> Result<FieldType, FieldError> {
+        let field = self.get_field(n)?;
+
+        let type_str = get_inheritable(&self.doc, field, b"FT")?
+            .as_name_str()
+            .map_err(|_| FieldError::Malformed("/FT is not a name".to_owned()))?;
+        Ok(if type_str == "Btn" {
+            let flags = ButtonFlags::from_bits_truncate(get_field_flags(&self.doc, field));
             if flags.intersects(ButtonFlags::RADIO | ButtonFlags::NO_TOGGLE_TO_OFF) {
                 FieldType::Radio
             } else if flags.intersects(ButtonFlags::PUSHBUTTON) {
@@ -213,7 +440,7 @@ impl Form {
                 FieldType::CheckBox
             }
         } else if type_str == "Ch" {
-            let flags = ChoiceFlags::from_bits_truncate(get_field_flags(field));
+            let flags = ChoiceFlags::from_bits_truncate(get_field_flags(&self.doc, field));
             if flags.intersects(ChoiceFlags::COBMO) {
                 FieldType::ComboBox
             } else {
@@ -221,32 +448,123 @@ impl Form {
             }
         } else if type_str == "Tx" {
             FieldType::Text
+        } else if type_str == "Sig" {
+            FieldType::Signature
         } else {
             FieldType::Unknown
-        }
+        })
     }
 
-    /// Gets the name of field of the given index
+    /// Gets the name of field of the given index.  Malformed names are reported as `None`
     ///
     /// # Panics
     /// This function will panic if the index is greater than the number of fields
     pub fn get_name(&self, n: usize) -> Option<String> {
-        // unwraps should be fine because load should have verified everything exists
-        let field = self
-            .doc
-            .objects
-            .get(&self.form_ids[n])
-            .unwrap()
-            .as_dict()
-            .unwrap();
+        match self.try_get_name(n) {
+            Ok(name) => name,
+            Err(FieldError::IndexOutOfRange(n)) => panic!("field index {} is out of range", n),
+            Err(_) => None,
+        }
+    }
+
+    /// Gets the name of field of the given index.
+    /// If the index is out of range or the name is malformed, returns FieldError
+    pub fn try_get_name(&self, n: usize) -> Result<Option<String>, FieldError> {
+        let field = self.get_field(n)?;
 
         // The "T" key refers to the name of the field
         match field.get(b"T") {
-            Ok(Object::String(data, _)) => String::from_utf8(data.clone()).ok(),
-            _ => None,
+            Ok(Object::String(data, _)) => decode_string(data).map(Some),
+            Ok(_) => Err(FieldError::Malformed("/T is not a string".to_owned())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Gets the fully qualified name of the field of the given index.  This is the partial name of
+    /// the field joined with the partial names of all of its ancestors, e.g.
+    /// `applicant.address.city`
+    ///
+    /// # Panics
+    /// This function will panic if the index is greater than the number of fields
+    pub fn get_full_name(&self, n: usize) -> Option<String> {
+        match self.get_field(n) {
+            Ok(field) => get_full_name(&self.doc, field),
+            Err(FieldError::IndexOutOfRange(n)) => panic!("field index {} is out of range", n),
+            Err(_) => None,
+        }
+    }
+
+    /// Finds the field with the fully qualified name `name` and returns the `ObjectId` of its
+    /// dictionary
+    pub fn find(&self, name: &str) -> Option<ObjectId> {
+        self.names.get(name).copied()
+    }
+
+    /// Returns the index of the field with the fully qualified name `name`, if there is one
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        let oid = self.find(name)?;
+        self.form_ids.iter().position(|&x| x == oid)
+    }
+
+    /// Gets the dictionary of the field of the given index
+    fn get_field(&self, n: usize) -> Result<&Dictionary, FieldError> {
+        let oid = self.form_ids.get(n).ok_or(FieldError::IndexOutOfRange(n))?;
+
+        Ok(self.doc.get_dictionary(*oid)?)
+    }
+
+    fn index_by_name(&self, name: &str) -> Result<usize, ValueError> {
+        self.index_of(name).ok_or(ValueError::NoSuchField)
+    }
+
+    /// Gets the widget annotations of the field of the given index, i.e. where and how the field
+    /// is shown on the pages of the document
+    ///
+    /// # Panics
+    /// This function will panic if the index is greater than the number of fields
+    pub fn get_widgets(&self, n: usize) -> Vec<Widget> {
+        match self.try_get_widgets(n) {
+            Ok(widgets) => widgets,
+            Err(_) => panic!("field index {} is out of range", n),
         }
     }
 
+    /// Gets the widget annotations of the field of the given index.
+    /// If the index is out of range, returns FieldError
+    pub fn try_get_widgets(&self, n: usize) -> Result<Vec<Widget>, FieldError> {
+        let widget_ids = self
+            .widget_ids
+            .get(n)
+            .ok_or(FieldError::IndexOutOfRange(n))?;
+
+        Ok(widget_ids
+            .iter()
+            .filter_map(|&oid| {
+                let widget = self.doc.get_dictionary(oid).ok()?;
+
+                Some(Widget {
+                    page: self.pages.get(&oid).copied().or_else(|| {
+                        widget
+                            .get(b"P")
+                            .and_then(Object::as_reference)
+                            .ok()
+                            .and_then(|page| self.pages.get(&page).copied())
+                    }),
+                    rect: get_rect(widget).unwrap_or_default(),
+                    appearance: widget
+                        .get(b"AP")
+                        .and_then(|ap| self.doc.dereference(ap))
+                        .and_then(|(_, ap)| ap.as_dict())
+                        .ok()
+                        .cloned(),
+                    flags: AnnotationFlags::from_bits_truncate(
+                        widget.get(b"F").and_then(Object::as_i64).unwrap_or(0) as u32,
+                    ),
+                })
+            })
+            .collect())
+    }
+
     /// Gets the types of all of the fields in the form
     pub fn get_all_types(&self) -> Vec<FieldType> {
         let mut res = Vec::with_capacity(self.len());
@@ -265,166 +583,212 @@ impl Form {
         res
     }
 
-    /// Gets the state of field of the given index
+    /// Gets the metadata of the field of the given index, such as its tooltip or default value.
+    /// Fields with malformed metadata are reported with empty metadata
+    ///
+    /// # Panics
+    /// This function will panic if the index is greater than the number of fields
+    pub fn get_info(&self, n: usize) -> FieldInfo {
+        match self.try_get_info(n) {
+            Ok(info) => info,
+            Err(FieldError::IndexOutOfRange(n)) => panic!("field index {} is out of range", n),
+            Err(_) => FieldInfo::default(),
+        }
+    }
+
+    /// Gets the metadata of the field of the given index, such as its tooltip or default value.
+    /// If the index is out of range or the metadata is malformed, returns FieldError
+    pub fn try_get_info(&self, n: usize) -> Result<FieldInfo, FieldError> {
+        let field = self.get_field(n)?;
+
+        let text = |key: &[u8]| match field.get(key) {
+            Ok(Object::String(s, _)) => decode_string(s).map(Some),
+            Ok(_) => Err(FieldError::Malformed(format!(
+                "/{} is not a string",
+                from_utf8(key).unwrap_or_default()
+            ))),
+            _ => Ok(None),
+        };
+
+        Ok(FieldInfo {
+            alternate_name: text(b"TU")?,
+            mapping_name: text(b"TM")?,
+            max_len: match get_inheritable(&self.doc, field, b"MaxLen") {
+                Ok(max_len) => Some(
+                    max_len
+                        .as_i64()
+                        .map_err(|_| FieldError::Malformed("/MaxLen is not an integer".to_owned()))?
+                        .max(0) as u32,
+                ),
+                _ => None,
+            },
+            alignment: get_alignment(&self.doc, field),
+            default_appearance: DefaultAppearance::of(&self.doc, field),
+            default_value: get_selection(get_inheritable(&self.doc, field, b"DV").ok())?,
+            flags: get_field_flags(&self.doc, field),
+        })
+    }
+
+    /// Gets the state of field of the given index.  Malformed fields are reported as
+    /// `FieldState::Unknown`
     ///
     /// # Panics
     /// This function will panic if the index is greater than the number of fields
     pub fn get_state(&self, n: usize) -> FieldState {
-        let field = self
-            .doc
-            .objects
-            .get(&self.form_ids[n])
-            .unwrap()
-            .as_dict()
-            .unwrap();
-        match self.get_type(n) {
+        match self.try_get_state(n) {
+            Ok(state) => state,
+            Err(FieldError::IndexOutOfRange(n)) => panic!("field index {} is out of range", n),
+            Err(_) => FieldState::Unknown,
+        }
+    }
+
+    /// Gets the state of field of the given index.
+    /// If the index is out of range or the field is malformed, returns FieldError
+    pub fn try_get_state(&self, n: usize) -> Result<FieldState, FieldError> {
+        let field = self.get_field(n)?;
+        Ok(match self.try_get_type(n)? {
             FieldType::Button => FieldState::Button,
             FieldType::Radio => FieldState::Radio {
-                selected: match field.get(b"V") {
-                    Ok(name) => name.as_name_str().unwrap().to_owned(),
-                    _ => match field.get(b"AS") {
-                        Ok(name) => name.as_name_str().unwrap().to_owned(),
-                        _ => "".to_owned(),
-                    },
-                },
-                options: self.get_possibilities(self.form_ids[n]),
-                readonly: is_read_only(field),
-                required: is_required(field),
-            },
-            FieldType::CheckBox => FieldState::CheckBox {
-                is_checked: match field.get(b"V") {
-                    Ok(name) => name.as_name_str().unwrap() == "Yes",
-                    _ => match field.get(b"AS") {
-                        Ok(name) => name.as_name_str().unwrap() == "Yes",
-                        _ => false,
-                    },
-                },
-                readonly: is_read_only(field),
-                required: is_required(field),
-            },
-            FieldType::ListBox => FieldState::ListBox {
-                // V field in a list box can be either text for one option, an array for many
-                // options, or null
-                selected: match field.get(b"V") {
-                    Ok(selection) => match *selection {
-                        Object::String(ref s, StringFormat::Literal) => {
-                            vec![str::from_utf8(&s).unwrap().to_owned()]
-                        }
-                        Object::Array(ref chosen) => {
-                            let mut res = Vec::new();
-                            for obj in chosen {
-                                if let Object::String(ref s, StringFormat::Literal) = *obj {
-                                    res.push(str::from_utf8(&s).unwrap().to_owned());
+                // Without a value, the selection is the state of the widget that is switched on
+                selected: match get_inheritable(&self.doc, field, b"V") {
+                    Ok(name) => decode_name(name)?,
+                    _ => {
+                        let mut selected = "".to_owned();
+                        for widget in self.widget_ids[n]
+                            .iter()
+                            .map(|&oid| self.doc.get_dictionary(oid))
+                        {
+                            if let Ok(name) = widget?.get(b"AS") {
+                                selected = decode_name(name)?;
+                                if selected != "Off" {
+                                    break;
                                 }
                             }
-                            res
                         }
-                        _ => Vec::new(),
-                    },
-                    _ => Vec::new(),
+                        selected
+                    }
                 },
-                // The options is an array of either text elements or arrays where the second
-                // element is what we want
-                options: match field.get(b"Opt") {
-                    Ok(&Object::Array(ref options)) => options
-                        .iter()
-                        .map(|x| match *x {
-                            Object::String(ref s, StringFormat::Literal) => {
-                                str::from_utf8(&s).unwrap().to_owned()
-                            }
-                            Object::Array(ref arr) => {
-                                if let Object::String(ref s, StringFormat::Literal) = &arr[1] {
-                                    str::from_utf8(&s).unwrap().to_owned()
-                                } else {
-                                    String::new()
-                                }
+                options: self.get_possibilities(n),
+                readonly: is_read_only(&self.doc, field),
+                required: is_required(&self.doc, field),
+            },
+            // The on state is named by the appearances of the widgets, so any state but Off is
+            // checked
+            FieldType::CheckBox => FieldState::CheckBox {
+                is_checked: match get_inheritable(&self.doc, field, b"V") {
+                    Ok(name) => decode_name(name)? != "Off",
+                    _ => {
+                        let mut is_checked = false;
+                        for widget in self.widget_ids[n]
+                            .iter()
+                            .map(|&oid| self.doc.get_dictionary(oid))
+                        {
+                            if let Ok(name) = widget?.get(b"AS") {
+                                is_checked |= decode_name(name)? != "Off";
                             }
-                            _ => String::new(),
-                        })
-                        .filter(|x| !x.is_empty())
-                        .collect(),
-                    _ => Vec::new(),
+                        }
+                        is_checked
+                    }
                 },
+                readonly: is_read_only(&self.doc, field),
+                required: is_required(&self.doc, field),
+            },
+            FieldType::ListBox => FieldState::ListBox {
+                selected: get_selection(get_inheritable(&self.doc, field, b"V").ok())?,
+                options: get_options(get_inheritable(&self.doc, field, b"Opt").ok())?,
                 multiselect: {
-                    let flags = ChoiceFlags::from_bits_truncate(get_field_flags(field));
+                    let flags = ChoiceFlags::from_bits_truncate(get_field_flags(&self.doc, field));
                     flags.intersects(ChoiceFlags::MULTISELECT)
                 },
-                readonly: is_read_only(field),
-                required: is_required(field),
+                readonly: is_read_only(&self.doc, field),
+                required: is_required(&self.doc, field),
             },
             FieldType::ComboBox => FieldState::ComboBox {
-                // V field in a list box can be either text for one option, an array for many
-                // options, or null
-                selected: match field.get(b"V") {
-                    Ok(selection) => match *selection {
-                        Object::String(ref s, StringFormat::Literal) => {
-                            vec![str::from_utf8(&s).unwrap().to_owned()]
-                        }
-                        Object::Array(ref chosen) => {
-                            let mut res = Vec::new();
-                            for obj in chosen {
-                                if let Object::String(ref s, StringFormat::Literal) = *obj {
-                                    res.push(str::from_utf8(&s).unwrap().to_owned());
-                                }
-                            }
-                            res
-                        }
-                        _ => Vec::new(),
-                    },
-                    _ => Vec::new(),
-                },
-                // The options is an array of either text elements or arrays where the second
-                // element is what we want
-                options: match field.get(b"Opt") {
-                    Ok(&Object::Array(ref options)) => options
-                        .iter()
-                        .map(|x| match *x {
-                            Object::String(ref s, StringFormat::Literal) => {
-                                str::from_utf8(&s).unwrap().to_owned()
-                            }
-                            Object::Array(ref arr) => {
-                                if let Object::String(ref s, StringFormat::Literal) = &arr[1] {
-                                    str::from_utf8(&s).unwrap().to_owned()
-                                } else {
-                                    String::new()
-                                }
-                            }
-                            _ => String::new(),
-                        })
-                        .filter(|x| !x.is_empty())
-                        .collect(),
-                    _ => Vec::new(),
-                },
+                selected: get_selection(get_inheritable(&self.doc, field, b"V").ok())?,
+                options: get_options(get_inheritable(&self.doc, field, b"Opt").ok())?,
                 editable: {
-                    let flags = ChoiceFlags::from_bits_truncate(get_field_flags(field));
+                    let flags = ChoiceFlags::from_bits_truncate(get_field_flags(&self.doc, field));
 
                     flags.intersects(ChoiceFlags::EDIT)
                 },
-                readonly: is_read_only(field),
-                required: is_required(field),
+                readonly: is_read_only(&self.doc, field),
+                required: is_required(&self.doc, field),
             },
-            FieldType::Text => FieldState::Text {
-                text: match field.get(b"V") {
-                    Ok(&Object::String(ref s, StringFormat::Literal)) => {
-                        str::from_utf8(&s.clone()).unwrap().to_owned()
+            FieldType::Text => {
+                let flags = TextFlags::from_bits_truncate(get_field_flags(&self.doc, field));
+
+                FieldState::Text {
+                    text: match get_inheritable(&self.doc, field, b"V") {
+                        Ok(Object::String(s, _)) => decode_string(s)?,
+                        _ => "".to_owned(),
+                    },
+                    multiline: flags.intersects(TextFlags::MULTILINE),
+                    password: flags.intersects(TextFlags::PASSWORD),
+                    file_select: flags.intersects(TextFlags::FILE_SELECT),
+                    comb: flags.intersects(TextFlags::COMB),
+                    do_not_scroll: flags.intersects(TextFlags::DO_NOT_SCROLL),
+                    rich_text: flags.intersects(TextFlags::RICH_TEXT),
+                    readonly: is_read_only(&self.doc, field),
+                    required: is_required(&self.doc, field),
+                }
+            }
+            FieldType::Signature => {
+                let signature = match get_inheritable(&self.doc, field, b"V") {
+                    Ok(Object::Dictionary(signature)) => Some(signature),
+                    Ok(Object::Null) | Err(_) => None,
+                    Ok(_) => {
+                        return Err(FieldError::Malformed(
+                            "/V of a signature field is not a dictionary".to_owned(),
+                        ))
                     }
-                    _ => "".to_owned(),
-                },
-                readonly: is_read_only(field),
-                required: is_required(field),
-            },
+                };
+
+                let text = |key: &[u8]| match signature.map(|signature| signature.get(key)) {
+                    Some(Ok(Object::String(s, _))) => decode_string(s).map(Some),
+                    _ => Ok(None),
+                };
+
+                FieldState::Signature {
+                    signed: signature.is_some(),
+                    signer: text(b"Name")?,
+                    signing_time: text(b"M")?,
+                    reason: text(b"Reason")?,
+                    location: text(b"Location")?,
+                    sub_filter: match signature.map(|signature| signature.get(b"SubFilter")) {
+                        Some(Ok(name)) => Some(decode_name(name)?),
+                        _ => None,
+                    },
+                    readonly: is_read_only(&self.doc, field),
+                    required: is_required(&self.doc, field),
+                }
+            }
             FieldType::Unknown => FieldState::Unknown,
-        }
+        })
+    }
+
+    /// Gets the state of the field with the fully qualified name `name`.
+    /// If there is no such field, returns ValueError
+    pub fn get_state_by_name(&self, name: &str) -> Result<FieldState, ValueError> {
+        Ok(self.get_state(self.index_by_name(name)?))
     }
 
     /// If the field at index `n` is a text field, fills in that field with the text `s`.
-    /// If it is not a text field, returns ValueError
+    /// If it is not a text field, or the text does not fit into the cells of a comb field,
+    /// returns ValueError
     ///
     /// # Panics
     /// Will panic if n is larger than the number of fields
     pub fn set_text(&mut self, n: usize, s: String) -> Result<(), ValueError> {
         match self.get_state(n) {
-            FieldState::Text { .. } => {
+            FieldState::Text { comb, .. } => {
+                if comb {
+                    if let Some(max_len) = self.get_info(n).max_len {
+                        if s.chars().count() > max_len as usize {
+                            return Err(ValueError::TooLong);
+                        }
+                    }
+                }
+
                 let field = self
                     .doc
                     .objects
@@ -433,10 +797,10 @@ impl Form {
                     .as_dict_mut()
                     .unwrap();
 
-                field.set("V", Object::string_literal(s.into_bytes()));
+                field.set("V", Object::string_literal(encode_text_string(&s)));
 
-                // Regenerate text appearance confoming the new text but ignore the result
-                let _ = self.regenerate_text_appearance(n);
+                // Regenerate text appearance confoming the new text
+                self.update_appearance(n);
 
                 Ok(())
             }
@@ -444,125 +808,289 @@ impl Form {
         }
     }
 
-    /// Regenerates the appearance for the field at index `n` due to an alteration of the
-    /// original TextField value, the AP will be updated accordingly.
+    /// Same as `set_text`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns ValueError
+    pub fn set_text_by_name(&mut self, name: &str, s: String) -> Result<(), ValueError> {
+        self.set_text(self.index_by_name(name)?, s)
+    }
+
+    /// Regenerates the appearance for the text field, combo box or list box at index `n` due to an
+    /// alteration of its value, the AP will be updated accordingly.
     ///
     /// # Incomplete
     /// This function is not exhaustive as not parse the original TextField orientation
-    /// or the text alignment and other kind of enrichments, also doesn't discover for
-    /// the global document DA.
+    /// and other kind of enrichments.
     ///
     /// A more sophisticated parser is needed here
-    fn regenerate_text_appearance(&mut self, n: usize) -> Result<(), lopdf::Error> {
-        let field = {
-            self.doc
-                .objects
-                .get(&self.form_ids[n])
-                .unwrap()
-                .as_dict()
-                .unwrap()
-        };
+    fn regenerate_appearance(&mut self, n: usize) -> Result<(), lopdf::Error> {
+        let field = self.doc.get_dictionary(self.form_ids[n])?;
 
         // The value of the object (should be a string)
-        let value = field.get(b"V")?.to_owned();
+        let value = match get_inheritable(&self.doc, field, b"V") {
+            Ok(Object::String(bytes, _)) => decode_text_string(bytes).unwrap_or_default(),
+            _ => String::new(),
+        };
+
+        let (options, selected, top_index) = match self.get_state(n) {
+            FieldState::ListBox {
+                options, selected, ..
+            } => {
+                let top_index = get_inheritable(&self.doc, field, b"TI")
+                    .and_then(Object::as_i64)
+                    .unwrap_or(0);
+                (options, selected, Some(top_index.max(0) as usize))
+            }
+            _ => (Vec::new(), Vec::new(), None),
+        };
+        let contents = match top_index {
+            Some(top_index) => Contents::List {
+                options: &options,
+                selected: &selected,
+                top_index,
+            },
+            None => Contents::Text(&value),
+        };
 
-        // The default appearance of the object (should be a string)
-        let da = field.get(b"DA")?.to_owned();
+        let embedded_font = self.field_font(n).map(str::to_owned);
 
-        // The default appearance of the object (should be a string)
-        let rect = field
-            .get(b"Rect")?
-            .as_array()?
-            .iter()
-            .map(|object| {
-                object
-                    .as_f64()
-                    .unwrap_or(object.as_i64().unwrap_or(0) as f64) as f32
-            })
-            .collect::<Vec<_>>();
+        // Every widget of the field shows the same value
+        let mut result = Ok(());
+        for oid in self.widget_ids[n].clone() {
+            if let Err(err) =
+                self.regenerate_widget_appearance(oid, &contents, embedded_font.as_deref())
+            {
+                result = Err(err);
+            }
+        }
 
-        // Gets the object stream
-        let object_id = field.get(b"AP")?.as_dict()?.get(b"N")?.as_reference()?;
-        let stream = self.doc.get_object_mut(object_id)?.as_stream_mut()?;
+        result
+    }
 
-        // Decode and get the content, even if is compressed
-        let mut content = {
-            if let Ok(content) = stream.decompressed_content() {
-                Content::decode(&content)?
-            } else {
-                Content::decode(&stream.content)?
+    /// Regenerates the appearance of a single widget annotation of a text field or choice field
+    /// so that it shows `contents`, in the embedded font `embedded_font` if one is given
+    fn regenerate_widget_appearance(
+        &mut self,
+        oid: ObjectId,
+        contents: &Contents,
+        embedded_font: Option<&str>,
+    ) -> Result<(), lopdf::Error> {
+        let widget = self.doc.get_dictionary(oid)?;
+
+        // The default appearance of the object, or else the one of the form
+        let da = DefaultAppearance::of(&self.doc, widget).ok_or(lopdf::Error::DictKey)?;
+
+        // The rectangle of the widget on the page
+        let rect = get_rect(widget).ok_or(lopdf::Error::DictKey)?;
+
+        // Gets the object stream, which is created if the widget has none yet
+        let object_id = normal_appearance(&mut self.doc, oid, rect)?;
+
+        // The embedded fonts of an earlier appearance are dropped, so only the fonts that are
+        // shown get written
+        let font_ids = self.fonts.values().map(|font| font.id).collect::<Vec<_>>();
+        let stream = self.doc.get_object(object_id)?.as_stream()?;
+        if let Some(resources) = without_fonts(&self.doc, stream, &font_ids) {
+            let stream = self.doc.get_object_mut(object_id)?.as_stream_mut()?;
+            stream.dict.set("Resources", resources);
+        }
+
+        // An embedded font replaces the font of the default appearance, but keeps its size
+        let embedded_font = embedded_font.filter(|name| self.fonts.contains_key(*name));
+
+        let font_name = embedded_font.unwrap_or(&da.font);
+
+        // The font of the default appearance may only be found in the default resources of the
+        // form, in which case the stream gets a copy of it
+        if embedded_font.is_none() {
+            let stream = self.doc.get_object(object_id)?.as_stream()?;
+            if let Some(resources) = default_font_resources(&self.doc, stream, font_name) {
+                let stream = self.doc.get_object_mut(object_id)?.as_stream_mut()?;
+                stream.dict.set("Resources", resources);
             }
+        }
+
+        let widget = self.doc.get_dictionary(oid)?;
+
+        // Only text fields may have multiple lines or be comb fields
+        let flags = match contents {
+            Contents::Text(_) if !is_choice(&self.doc, widget) => {
+                TextFlags::from_bits_truncate(get_field_flags(&self.doc, widget))
+            }
+            _ => TextFlags::empty(),
         };
+        let multiline = flags.intersects(TextFlags::MULTILINE);
 
-        // Ignored operators
-        let ignored_operators = vec![
-            "bt", "tc", "tw", "tz", "g", "tm", "tr", "tf", "tj", "et", "q", "bmc", "emc",
-        ];
+        // Comb fields need a maximum length, which is the number of cells
+        let comb = match get_inheritable(&self.doc, widget, b"MaxLen").and_then(Object::as_i64) {
+            Ok(max_len)
+                if max_len > 0
+                    && flags.intersects(TextFlags::COMB)
+                    && !flags.intersects(
+                        TextFlags::MULTILINE | TextFlags::PASSWORD | TextFlags::FILE_SELECT,
+                    ) =>
+            {
+                Some(max_len as usize)
+            }
+            _ => None,
+        };
 
-        // Remove these ignored operators as we have to generate the text and fonts again
-        content.operations.retain(|operation| {
-            !ignored_operators.contains(&operation.operator.to_lowercase().as_str())
-        });
+        // The room for the text inside the padding
+        let frame = Frame::of_widget(&self.doc, widget);
+        let padding = frame.padding();
+        let width = rect[2] - rect[0] - 2.0 * padding;
+        let height = rect[3] - rect[1] - 2.0 * padding;
+
+        let stream = self.doc.get_object(object_id)?.as_stream()?;
+
+        let metrics = match embedded_font {
+            Some(name) => Metrics::Embedded(&self.fonts[name]),
+            None => find_font(&self.doc, stream, font_name)
+                .map_or(Metrics::WinAnsi(&HELVETICA), |font| {
+                    Metrics::of_font(&self.doc, font)
+                }),
+        };
+
+        // A font size of 0 means the text is sized to fit the field.  The characters of comb
+        // fields have cells of their own, so only the height limits them.  List boxes scroll
+        // instead, so their options are shown at the largest size, like Acrobat does
+        let font_size = match (da.font_size, contents) {
+            (size, _) if size > 0.0 => size,
+            (_, Contents::List { .. }) => MAX_AUTO_FONT_SIZE,
+            _ if comb.is_some() => auto_font_size("", &metrics, width, height, false),
+            (_, Contents::Text(value)) => auto_font_size(value, &metrics, width, height, multiline),
+        };
+
+        // Multiline text starts at the top of the box, single lines use the formula picked up
+        // from Poppler
+        let box_width = rect[2] - rect[0];
+        let dy = rect[3] - rect[1];
+        let y = if multiline || matches!(contents, Contents::List { .. }) {
+            dy - padding - font_size
+        } else if dy > 0.0 {
+            0.5 * dy - 0.4 * font_size
+        } else {
+            0.5 * font_size
+        };
+        let leading = LEADING * font_size;
+        let alignment = get_alignment(&self.doc, widget);
 
-        // Let's construct the text widget
+        // Place the text as runs at their positions: every character of a comb field in its
+        // cell, otherwise every line aligned on its own.  Multiline text is broken into lines
+        // that fit between the padding.  List boxes show an option per line, from the top index
+        // on as far as they fit, and highlight the rows of the selected ones
+        let mut highlights = Vec::new();
+        let runs = if let (Some(max_len), Contents::Text(value)) = (comb, contents) {
+            layout::comb(value, &metrics, font_size, box_width, max_len, alignment)
+                .into_iter()
+                .map(|(text, x)| (text, x, y))
+                .collect::<Vec<_>>()
+        } else {
+            let lines = match contents {
+                Contents::Text(value) if multiline => wrap(value, &metrics, font_size, width),
+                Contents::Text(value) => vec![value.to_string()],
+                Contents::List {
+                    options,
+                    selected,
+                    top_index,
+                } => {
+                    let rows = (height / leading).ceil() as usize;
+                    let visible = options.iter().skip(*top_index).take(rows);
+                    for (i, option) in visible.clone().enumerate() {
+                        if selected.contains(option) {
+                            let top = dy - padding - i as f32 * leading;
+                            highlights.push(rectangle(padding, top - leading, width, leading));
+                        }
+                    }
+                    visible.cloned().collect()
+                }
+            };
+            lines
+                .into_iter()
+                .enumerate()
+                .map(|(i, line)| {
+                    let x = align(
+                        metrics.text_width(&line, font_size),
+                        box_width,
+                        padding,
+                        alignment,
+                    );
+                    (line, x, y - i as f32 * leading)
+                })
+                .collect()
+        };
+
+        let fonts = &mut self.fonts;
+
+        // The embedded font has to be part of the resources.  Otherwise the text is encoded in
+        // WinAnsiEncoding, so the font has to use it as well
+        let (texts, resources) = match embedded_font.and_then(|name| fonts.get_mut(name)) {
+            Some(font) => (
+                runs.iter()
+                    .map(|(text, _, _)| {
+                        Object::String(font.encode(text), StringFormat::Hexadecimal)
+                    })
+                    .collect::<Vec<_>>(),
+                Some(font_resources(&self.doc, stream, font_name, font.id.into())),
+            ),
+            None => (
+                runs.iter()
+                    .map(|(text, _, _)| Object::string_literal(encode_win_ansi(text)))
+                    .collect(),
+                win_ansi_resources(&self.doc, stream, font_name),
+            ),
+        };
+
+        let stream = self.doc.get_object_mut(object_id)?.as_stream_mut()?;
+        if let Some(resources) = resources {
+            stream.dict.set("Resources", resources);
+        }
+
+        // The background and border are drawn anew, as the old stream may have been generated
+        // for different ones
+        let mut content = Content {
+            operations: frame.draw(box_width, dy),
+        };
+
+        // Let's construct the text widget, which must not draw over the border
         content.operations.append(&mut vec![
             Operation::new("BMC", vec!["Tx".into()]),
             Operation::new("q", vec![]),
-            Operation::new("BT", vec![]),
+            rectangle(
+                padding - 1.0,
+                padding - 1.0,
+                box_width - 2.0 * (padding - 1.0),
+                dy - 2.0 * (padding - 1.0),
+            ),
+            Operation::new("W", vec![]),
+            Operation::new("n", vec![]),
         ]);
 
-        let font = parse_font(match da {
-            Object::String(ref bytes, _) => Some(from_utf8(bytes)?),
-            _ => None,
-        });
-
-        // Define some helping font variables
-        let font_name = (font.0).0;
-        let font_size = (font.0).1;
-        let font_color = font.1;
+        if !highlights.is_empty() {
+            content.operations.push(fill_color(&HIGHLIGHT));
+            content.operations.append(&mut highlights);
+            content.operations.push(Operation::new("f", vec![]));
+        }
+        content.operations.push(Operation::new("BT", vec![]));
 
         // Set the font type and size and color
         content.operations.append(&mut vec![
-            Operation::new("Tf", vec![font_name.into(), font_size.into()]),
-            Operation::new(
-                font_color.0,
-                match font_color.0 {
-                    "k" => vec![
-                        font_color.1.into(),
-                        font_color.2.into(),
-                        font_color.3.into(),
-                        font_color.4.into(),
-                    ],
-                    "rg" => vec![
-                        font_color.1.into(),
-                        font_color.2.into(),
-                        font_color.3.into(),
-                    ],
-                    _ => vec![font_color.1.into()],
-                },
-            ),
+            Operation::new("Tf", vec![font_name.into(), number(font_size)]),
+            da.color_operation(),
         ]);
 
-        // Calcolate the text offset
-        let x = 2.0; // Suppose this fixed offset as we should have known the border here
-
-        // Formula picked up from Poppler
-        let dy = rect[1] - rect[3];
-        let y = if dy > 0.0 {
-            0.5 * dy - 0.4 * font_size as f32
-        } else {
-            0.5 * font_size as f32
-        };
-
-        // Set the text bounds, first are fixed at "1 0 0 1" and then the calculated x,y
-        content.operations.append(&mut vec![Operation::new(
-            "Tm",
-            vec![1.into(), 0.into(), 0.into(), 1.into(), x.into(), y.into()],
-        )]);
+        for (text, (_, x, y)) in texts.into_iter().zip(runs) {
+            // Set the text bounds, first are fixed at "1 0 0 1" and then the calculated x,y
+            content.operations.append(&mut vec![
+                Operation::new(
+                    "Tm",
+                    vec![1.into(), 0.into(), 0.into(), 1.into(), number(x), number(y)],
+                ),
+                Operation::new("Tj", vec![text]),
+            ]);
+        }
 
-        // Set the text value and some finalizing operations
+        // Set some finalizing operations
         content.operations.append(&mut vec![
-            Operation::new("Tj", vec![value]),
             Operation::new("ET", vec![]),
             Operation::new("Q", vec![]),
             Operation::new("EMC", vec![]),
@@ -586,6 +1114,14 @@ impl Form {
     pub fn set_check_box(&mut self, n: usize, is_checked: bool) -> Result<(), ValueError> {
         match self.get_state(n) {
             FieldState::CheckBox { .. } => {
+                // The on state is named by the appearances of the widgets, which may be separate
+                // from the field itself
+                let on = match self.widget_ids[n].first() {
+                    Some(&oid) => self.doc.get_dictionary(oid).map(get_on_value),
+                    None => self.doc.get_dictionary(self.form_ids[n]).map(get_on_value),
+                }
+                .unwrap_or_else(|_| "Yes".to_owned());
+
                 let field = self
                     .doc
                     .objects
@@ -594,15 +1130,15 @@ impl Form {
                     .as_dict_mut()
                     .unwrap();
 
-                let on = get_on_value(field);
-                let state = Object::Name(
-                    if is_checked { on.as_str() } else { "Off" }
-                        .to_owned()
-                        .into_bytes(),
-                );
+                let state = if is_checked { on.as_str() } else { "Off" };
+
+                field.set("V", Object::Name(state.to_owned().into_bytes()));
 
-                field.set("V", state.clone());
-                field.set("AS", state);
+                // Widgets without appearances for both states would not show the change
+                for oid in self.widget_ids[n].clone() {
+                    let _ = button_appearances(&mut self.doc, oid, &on, false);
+                }
+                self.set_appearance_state(n, state);
 
                 Ok(())
             }
@@ -610,6 +1146,16 @@ impl Form {
         }
     }
 
+    /// Same as `set_check_box`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns ValueError
+    pub fn set_check_box_by_name(
+        &mut self,
+        name: &str,
+        is_checked: bool,
+    ) -> Result<(), ValueError> {
+        self.set_check_box(self.index_by_name(name)?, is_checked)
+    }
+
     /// If the field at index `n` is a radio field, toggles the radio button based on the value
     /// `choice`
     /// If it is not a radio button field or the choice is not a valid option, returns ValueError
@@ -627,7 +1173,13 @@ impl Form {
                         .unwrap()
                         .as_dict_mut()
                         .unwrap();
-                    field.set("V", Object::Name(choice.into_bytes()));
+                    field.set("V", Object::Name(choice.clone().into_bytes()));
+
+                    // The options are the on states of the widgets, in the same order
+                    for (oid, option) in self.widget_ids[n].clone().into_iter().zip(&options) {
+                        let _ = button_appearances(&mut self.doc, oid, option, true);
+                    }
+                    self.set_appearance_state(n, &choice);
                     Ok(())
                 } else {
                     Err(ValueError::InvalidSelection)
@@ -637,6 +1189,12 @@ impl Form {
         }
     }
 
+    /// Same as `set_radio`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns ValueError
+    pub fn set_radio_by_name(&mut self, name: &str, choice: String) -> Result<(), ValueError> {
+        self.set_radio(self.index_by_name(name)?, choice)
+    }
+
     /// If the field at index `n` is a listbox field, selects the options in `choice`
     /// If it is not a listbox field or one of the choices is not a valid option, or if too many choices are selected, returns ValueError
     ///
@@ -649,7 +1207,7 @@ impl Form {
                 multiselect,
                 ..
             } => {
-                if choices.iter().fold(true, |a, h| options.contains(h) && a) {
+                if choices.iter().all(|h| options.contains(h)) {
                     if !multiselect && choices.len() > 1 {
                         Err(ValueError::TooManySelected)
                     } else {
@@ -665,7 +1223,7 @@ impl Form {
                             1 => field.set(
                                 "V",
                                 Object::String(
-                                    choices[0].clone().into_bytes(),
+                                    encode_text_string(&choices[0]),
                                     StringFormat::Literal,
                                 ),
                             ),
@@ -676,7 +1234,7 @@ impl Form {
                                         .iter()
                                         .map(|x| {
                                             Object::String(
-                                                x.clone().into_bytes(),
+                                                encode_text_string(x),
                                                 StringFormat::Literal,
                                             )
                                         })
@@ -684,6 +1242,10 @@ impl Form {
                                 ),
                             ),
                         };
+
+                        // Regenerate the appearance to show the new selection
+                        self.update_appearance(n);
+
                         Ok(())
                     }
                 } else {
@@ -694,6 +1256,16 @@ impl Form {
         }
     }
 
+    /// Same as `set_list_box`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns ValueError
+    pub fn set_list_box_by_name(
+        &mut self,
+        name: &str,
+        choices: Vec<String>,
+    ) -> Result<(), ValueError> {
+        self.set_list_box(self.index_by_name(name)?, choices)
+    }
+
     /// If the field at index `n` is a combobox field, selects the options in `choice`
     /// If it is not a combobox field or one of the choices is not a valid option, or if too many choices are selected, returns ValueError
     ///
@@ -714,8 +1286,12 @@ impl Form {
                         .unwrap();
                     field.set(
                         "V",
-                        Object::String(choice.into_bytes(), StringFormat::Literal),
+                        Object::String(encode_text_string(&choice), StringFormat::Literal),
                     );
+
+                    // Regenerate the appearance to show the new choice
+                    self.update_appearance(n);
+
                     Ok(())
                 } else {
                     Err(ValueError::InvalidSelection)
@@ -725,50 +1301,137 @@ impl Form {
         }
     }
 
+    /// Same as `set_combo_box`, but the field is identified by its fully qualified name.
+    /// If there is no such field, returns ValueError
+    pub fn set_combo_box_by_name(&mut self, name: &str, choice: String) -> Result<(), ValueError> {
+        self.set_combo_box(self.index_by_name(name)?, choice)
+    }
+
+    /// Gets how the appearances of filled in fields are produced
+    pub fn fill_mode(&self) -> FillMode {
+        self.fill_mode
+    }
+
+    /// Sets how the appearances of the fields filled in from now on are produced, and how the
+    /// form is saved by `save` and `save_to`
+    pub fn set_fill_mode(&mut self, mode: FillMode) {
+        self.fill_mode = mode;
+    }
+
     /// Saves the form to the specified path
     pub fn save<P: AsRef<Path>>(&mut self, path: P) -> Result<(), io::Error> {
-        self.doc.save(path).map(|_| ())
+        self.save_with_fill_mode(path, self.fill_mode)
     }
 
     /// Saves the form to the specified path
     pub fn save_to<W: Write>(&mut self, target: &mut W) -> Result<(), io::Error> {
+        self.save_to_with_fill_mode(target, self.fill_mode)
+    }
+
+    /// Saves the form to the specified path in the fill mode `mode` rather than the one of the
+    /// form.  Fields filled in while appearances were left to the viewer get them generated now,
+    /// unless `mode` is `FillMode::NeedAppearances`
+    pub fn save_with_fill_mode<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        mode: FillMode,
+    ) -> Result<(), io::Error> {
+        self.prepare_save(mode);
+        self.doc.save(path).map(|_| ())
+    }
+
+    /// Saves the form to the specified target in the fill mode `mode` rather than the one of the
+    /// form, like `save_with_fill_mode`
+    pub fn save_to_with_fill_mode<W: Write>(
+        &mut self,
+        target: &mut W,
+        mode: FillMode,
+    ) -> Result<(), io::Error> {
+        self.prepare_save(mode);
         self.doc.save_to(target)
     }
 
-    fn get_possibilities(&self, oid: ObjectId) -> Vec<String> {
-        let mut res = Vec::new();
-        let kids_obj = self
-            .doc
-            .objects
-            .get(&oid)
-            .unwrap()
-            .as_dict()
-            .unwrap()
-            .get(b"Kids");
-        if let Ok(&Object::Array(ref kids)) = kids_obj {
-            for (i, kid) in kids.iter().enumerate() {
-                let mut found = false;
-                if let Ok(&Object::Dictionary(ref appearance_states)) =
-                    kid.deref(&self.doc).unwrap().as_dict().unwrap().get(b"AP")
-                {
-                    if let Ok(&Object::Dictionary(ref normal_appearance)) =
-                        appearance_states.get(b"N")
-                    {
-                        for (key, _) in normal_appearance {
-                            if key != b"Off" {
-                                res.push(from_utf8(key).unwrap_or("").to_owned());
-                                found = true;
-                                break;
-                            }
-                        }
-                    }
-                }
+    /// Regenerates the appearance of the field at index `n` after its value changed, or leaves it
+    /// to the viewer, depending on the fill mode
+    fn update_appearance(&mut self, n: usize) {
+        if self.fill_mode == FillMode::NeedAppearances {
+            self.stale_appearances.insert(n);
+        } else {
+            self.stale_appearances.remove(&n);
+            // Fields without a usable appearance keep the old one, so the result is ignored
+            let _ = self.regenerate_appearance(n);
+        }
+    }
 
-                if !found {
-                    res.push(i.to_string());
+    /// Brings the appearances and the `/NeedAppearances` flag in line with `mode`, and writes the
+    /// embedded fonts
+    fn prepare_save(&mut self, mode: FillMode) {
+        if mode != FillMode::NeedAppearances {
+            for n in std::mem::take(&mut self.stale_appearances) {
+                let _ = self.regenerate_appearance(n);
+            }
+        }
+
+        if let Some(acroform) = get_acroform_mut(&mut self.doc) {
+            match mode {
+                FillMode::Generate => {}
+                FillMode::GenerateAndClearNeedAppearances => {
+                    acroform.remove(b"NeedAppearances");
                 }
+                FillMode::NeedAppearances => acroform.set("NeedAppearances", true),
+            }
+        }
+
+        self.write_fonts();
+    }
+
+    /// Sets the appearance state (`/AS`) of every widget of the button field at index `n` to
+    /// `state`.  Widgets that have no appearance for `state`, such as the other buttons of a radio
+    /// group, are turned off instead
+    fn set_appearance_state(&mut self, n: usize, state: &str) {
+        for &oid in &self.widget_ids[n] {
+            if let Ok(widget) = self.doc.get_object_mut(oid).and_then(Object::as_dict_mut) {
+                let has_state = match widget.get(b"AP").and_then(|ap| ap.as_dict()?.get(b"N")) {
+                    Ok(Object::Dictionary(normal_appearance)) => {
+                        normal_appearance.has(state.as_bytes())
+                    }
+                    _ => true,
+                };
+
+                let state = if has_state { state } else { "Off" };
+                widget.set("AS", Object::Name(state.to_owned().into_bytes()));
             }
         }
+    }
+
+    /// The on states of the widgets of the radio field of the given index, in the same order as
+    /// its widgets.  A widget without an on state is named by its position instead
+    fn get_possibilities(&self, n: usize) -> Vec<String> {
+        let mut res = Vec::new();
+        for (i, &oid) in self.widget_ids[n].iter().enumerate() {
+            let on_state = self
+                .doc
+                .get_dictionary(oid)
+                .and_then(|widget| widget.get(b"AP"))
+                .and_then(|ap| self.doc.dereference(ap))
+                .and_then(|(_, ap)| ap.as_dict())
+                .and_then(|ap| ap.get(b"N"))
+                .and_then(|normal| self.doc.dereference(normal))
+                .and_then(|(_, normal)| normal.as_dict())
+                .ok()
+                .and_then(|normal| {
+                    normal
+                        .iter()
+                        .map(|(key, _)| key)
+                        .find(|&key| key != b"Off")
+                        .and_then(|key| from_utf8(key).ok())
+                });
+
+            res.push(match on_state {
+                Some(on_state) => on_state.to_owned(),
+                None => i.to_string(),
+            });
+        }
 
         res
     }
@@ -1,6 +1,11 @@
-use lopdf::{Dictionary, Object};
+use std::collections::HashSet;
 
-use crate::from_utf8;
+use lopdf::{dictionary, Dictionary, Document, Object, Stream};
+
+use crate::encoding::decode_text_string;
+use crate::font::font_resources;
+use crate::metrics::standard_font;
+use crate::{from_utf8, Alignment, FieldError};
 
 bitflags! {
     pub struct FieldFlags: u32 {
@@ -30,24 +35,221 @@ bitflags! {
     }
 }
 
-pub fn is_read_only(field: &Dictionary) -> bool {
-    let flags = FieldFlags::from_bits_truncate(get_field_flags(field));
+bitflags! {
+    pub struct TextFlags: u32 {
+        const MULTILINE         = 0x1000;
+        const PASSWORD          = 0x2000;
+        const FILE_SELECT       = 0x100000;
+        const DO_NOT_SPELLCHECK = 0x400000;
+        const DO_NOT_SCROLL     = 0x800000;
+        const COMB              = 0x1000000;
+        const RICH_TEXT         = 0x2000000;
+    }
+}
+
+bitflags! {
+    /// The annotation flags (`/F`) of a widget annotation
+    pub struct AnnotationFlags: u32 {
+        const INVISIBLE         = 0x1;
+        const HIDDEN            = 0x2;
+        const PRINT             = 0x4;
+        const NO_ZOOM           = 0x8;
+        const NO_ROTATE         = 0x10;
+        const NO_VIEW           = 0x20;
+        const READ_ONLY         = 0x40;
+        const LOCKED            = 0x80;
+        const TOGGLE_NO_VIEW    = 0x100;
+        const LOCKED_CONTENTS   = 0x200;
+    }
+}
+
+pub fn is_read_only(doc: &Document, field: &Dictionary) -> bool {
+    let flags = FieldFlags::from_bits_truncate(get_field_flags(doc, field));
 
     flags.intersects(FieldFlags::READONLY)
 }
 
-pub fn is_required(field: &Dictionary) -> bool {
-    let flags = FieldFlags::from_bits_truncate(get_field_flags(field));
+pub fn is_required(doc: &Document, field: &Dictionary) -> bool {
+    let flags = FieldFlags::from_bits_truncate(get_field_flags(doc, field));
 
     flags.intersects(FieldFlags::REQUIRED)
 }
 
-pub fn get_field_flags(field: &Dictionary) -> u32 {
-    field
-        .get(b"Ff")
-        .unwrap_or(&Object::Integer(0))
-        .as_i64()
-        .unwrap() as u32
+/// Whether the field is a choice field, a list box or combo box, by its possibly inherited type
+pub fn is_choice(doc: &Document, field: &Dictionary) -> bool {
+    matches!(
+        get_inheritable(doc, field, b"FT").and_then(Object::as_name),
+        Ok(b"Ch")
+    )
+}
+
+pub fn get_field_flags(doc: &Document, field: &Dictionary) -> u32 {
+    get_inheritable(doc, field, b"Ff")
+        .and_then(Object::as_i64)
+        .unwrap_or(0) as u32
+}
+
+/// Whether the dictionary is a widget annotation, as opposed to a pure field dictionary
+pub fn is_widget(dict: &Dictionary) -> bool {
+    dict.get(b"Subtype")
+        .and_then(Object::as_name)
+        .map_or(dict.has(b"Rect"), |subtype| subtype == b"Widget")
+}
+
+/// Gets the normalized rectangle (`/Rect`) of an annotation as `[llx, lly, urx, ury]`
+pub fn get_rect(annot: &Dictionary) -> Option<[f32; 4]> {
+    let rect = annot
+        .get(b"Rect")
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .map(|object| {
+            object
+                .as_f64()
+                .unwrap_or(object.as_i64().unwrap_or(0) as f64) as f32
+        })
+        .collect::<Vec<_>>();
+
+    if rect.len() < 4 {
+        return None;
+    }
+
+    Some([
+        rect[0].min(rect[2]),
+        rect[1].min(rect[3]),
+        rect[0].max(rect[2]),
+        rect[1].max(rect[3]),
+    ])
+}
+
+/// Decodes the bytes of a text string, which is either UTF-16BE or PDFDocEncoding
+pub fn decode_string(bytes: &[u8]) -> Result<String, FieldError> {
+    decode_text_string(bytes)
+        .ok_or_else(|| FieldError::Malformed("a text string is not validly encoded".to_owned()))
+}
+
+/// Decodes a name object such as the value of a button field
+pub fn decode_name(name: &Object) -> Result<String, FieldError> {
+    name.as_name_str()
+        .map(str::to_owned)
+        .map_err(|_| FieldError::Malformed("a button value is not a valid name".to_owned()))
+}
+
+/// Gets the selected entries from the value (`/V`) or default value (`/DV`) of a field, which can
+/// be either text or a name for one option, an array for many options, or null.  Text may be
+/// written as literal or hexadecimal string
+pub fn get_selection(value: Option<&Object>) -> Result<Vec<String>, FieldError> {
+    match value {
+        Some(Object::String(s, _)) => Ok(vec![decode_string(s)?]),
+        Some(name @ Object::Name(_)) => Ok(vec![decode_name(name)?]),
+        Some(Object::Array(chosen)) => {
+            let mut res = Vec::new();
+            for obj in chosen {
+                if let Object::String(s, _) = obj {
+                    res.push(decode_string(s)?);
+                }
+            }
+            Ok(res)
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+/// Gets the texts of the options (`/Opt`) of a choice field.  The options is an array of either
+/// text elements or arrays where the second element is what we want.  Text may be written as
+/// literal or hexadecimal string
+pub fn get_options(opt: Option<&Object>) -> Result<Vec<String>, FieldError> {
+    let mut res = Vec::new();
+    if let Some(Object::Array(options)) = opt {
+        for option in options {
+            let text = match option {
+                Object::String(s, _) => Some(s),
+                Object::Array(pair) => match pair.get(1) {
+                    Some(Object::String(s, _)) => Some(s),
+                    _ => None,
+                },
+                _ => None,
+            };
+
+            if let Some(text) = text {
+                let text = decode_string(text)?;
+                if !text.is_empty() {
+                    res.push(text);
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Gets the alignment of the text of a field from its quadding (`/Q`), possibly inherited
+pub fn get_alignment(doc: &Document, field: &Dictionary) -> Alignment {
+    match get_inheritable(doc, field, b"Q").and_then(Object::as_i64) {
+        Ok(1) => Alignment::Center,
+        Ok(2) => Alignment::Right,
+        _ => Alignment::Left,
+    }
+}
+
+/// The deepest `/Parent` chain that is followed before giving up, guarding against cycles
+const MAX_PARENT_DEPTH: usize = 64;
+
+/// Builds the fully qualified name of the field by joining its partial name (`/T`) with the
+/// partial names of all of its ancestors, separated by periods.  Returns `None` if the
+/// `/Parent` chain forms a cycle, as the field has no proper name then
+pub fn get_full_name(doc: &Document, field: &Dictionary) -> Option<String> {
+    let mut names = Vec::new();
+    let mut visited = HashSet::new();
+    let mut current = field;
+
+    loop {
+        if let Ok(Object::String(data, _)) = current.get(b"T") {
+            names.push(decode_text_string(data)?);
+        }
+
+        let parent = match current.get(b"Parent").and_then(Object::as_reference) {
+            Ok(parent) => parent,
+            Err(_) => break,
+        };
+        if !visited.insert(parent) {
+            return None;
+        }
+        current = match doc.get_dictionary(parent) {
+            Ok(dict) => dict,
+            Err(_) => break,
+        };
+    }
+
+    if names.is_empty() {
+        None
+    } else {
+        names.reverse();
+        Some(names.join("."))
+    }
+}
+
+/// Gets the value of an inheritable field attribute such as `/FT`, `/Ff`, `/V`, `/DA` or `/Opt`.
+/// If the field does not define the attribute itself, it is looked up in its ancestors through
+/// the `/Parent` chain.  Indirect values are dereferenced
+pub fn get_inheritable<'a>(
+    doc: &'a Document,
+    field: &'a Dictionary,
+    key: &[u8],
+) -> lopdf::Result<&'a Object> {
+    let mut current = field;
+
+    for _ in 0..MAX_PARENT_DEPTH {
+        if let Ok(value) = current.get(key) {
+            return doc.dereference(value).map(|(_, value)| value);
+        }
+
+        current = current
+            .get(b"Parent")
+            .and_then(Object::as_reference)
+            .and_then(|parent| doc.get_dictionary(parent))?;
+    }
+
+    Err(lopdf::Error::ReferenceLimit)
 }
 
 pub fn get_on_value(field: &Dictionary) -> String {
@@ -71,57 +273,138 @@ pub fn get_on_value(field: &Dictionary) -> String {
     option.unwrap_or("Yes".into())
 }
 
-pub fn parse_font(font_string: Option<&str>) -> ((&str, i32), (&str, i32, i32, i32, i32)) {
-    // The default font object (/Helv 12 Tf 0 g)
-    let default_font = ("Helv", 12);
-    let default_color = ("g", 0, 0, 0, 0);
-
-    // Build the font basing on the default appearance, if exists, if not,
-    // assume a default font (surely to be improved!)
-    match font_string {
-        Some(font_string) => {
-            let font = font_string
-                .trim_start_matches('/')
-                .split("Tf")
-                .collect::<Vec<_>>();
-
-            if font.len() < 2 {
-                (default_font, default_color)
-            } else {
-                let font_family = font[0].trim().split(' ').collect::<Vec<_>>();
-                let font_color = font[1].trim().split(' ').collect::<Vec<_>>();
-
-                let font = if font_family.len() >= 2 {
-                    (font_family[0], font_family[1].parse::<i32>().unwrap_or(0))
-                } else {
-                    default_font
-                };
-
-                let color = if font_color.len() == 2 {
-                    ("g", font_color[0].parse::<i32>().unwrap_or(0), 0, 0, 0)
-                } else if font_color.len() == 4 {
-                    (
-                        "rg",
-                        font_color[0].parse::<i32>().unwrap_or(0),
-                        font_color[1].parse::<i32>().unwrap_or(0),
-                        font_color[2].parse::<i32>().unwrap_or(0),
-                        0,
-                    )
-                } else if font_color.len() == 5 {
-                    (
-                        "k",
-                        font_color[0].parse::<i32>().unwrap_or(0),
-                        font_color[1].parse::<i32>().unwrap_or(0),
-                        font_color[2].parse::<i32>().unwrap_or(0),
-                        font_color[3].parse::<i32>().unwrap_or(0),
-                    )
-                } else {
-                    default_color
-                };
-
-                (font, color)
-            }
+/// Makes sure the font `font_name` of an appearance stream uses WinAnsiEncoding, which generated
+/// text is encoded in.  Returns the updated resources of the stream if they had to change.  The
+/// font is copied rather than changed in place, as other appearances may share it
+pub fn win_ansi_resources(doc: &Document, stream: &Stream, font_name: &str) -> Option<Dictionary> {
+    let deref_dict = |object: &Object| -> Option<Dictionary> {
+        doc.dereference(object).ok()?.1.as_dict().ok().cloned()
+    };
+
+    let mut resources = deref_dict(stream.dict.get(b"Resources").ok()?)?;
+    let mut fonts = deref_dict(resources.get(b"Font").ok()?)?;
+    let mut font = deref_dict(fonts.get(font_name.as_bytes()).ok()?)?;
+
+    // Only simple, non-symbolic fonts can be switched to another encoding
+    let subtype = match font.get(b"Subtype").and_then(Object::as_name) {
+        Ok(subtype @ b"Type1") | Ok(subtype @ b"TrueType") => subtype.to_vec(),
+        _ => return None,
+    };
+    let base_font = match font.get(b"BaseFont").and_then(Object::as_name) {
+        Ok(b"Symbol") | Ok(b"ZapfDingbats") => return None,
+        Ok(base_font) => base_font.to_vec(),
+        Err(_) => return None,
+    };
+
+    let encoding = font.get(b"Encoding").ok();
+    if let Some(Object::Name(name)) = encoding {
+        if name == b"WinAnsiEncoding" {
+            return None;
         }
-        _ => (default_font, default_color),
     }
+
+    if standard_font(doc, &font).is_some() {
+        // Standard fonts get a fresh copy, as their encoding may have differences, e.g. the
+        // PDFDocEncoding of the /Helv Acrobat puts into forms, and their widths follow it
+        font = dictionary! {
+            "Type" => "Font",
+            "Subtype" => Object::Name(subtype),
+            "BaseFont" => Object::Name(base_font),
+        };
+    } else if let Some(Object::Reference(_)) | Some(Object::Dictionary(_)) = encoding {
+        // Encoding dictionaries with differences of embedded fonts are left alone
+        return None;
+    }
+
+    font.set("Encoding", Object::Name(b"WinAnsiEncoding".to_vec()));
+    fonts.set(font_name, Object::Dictionary(font));
+    resources.set("Font", Object::Dictionary(fonts));
+    Some(resources)
+}
+
+/// Gets the interactive form dictionary (`/AcroForm`) of the document
+pub fn get_acroform(doc: &Document) -> Option<&Dictionary> {
+    let catalog = doc
+        .trailer
+        .get(b"Root")
+        .and_then(|root| doc.dereference(root))
+        .and_then(|(_, root)| root.as_dict())
+        .ok()?;
+
+    catalog
+        .get(b"AcroForm")
+        .and_then(|acroform| doc.dereference(acroform))
+        .and_then(|(_, acroform)| acroform.as_dict())
+        .ok()
+}
+
+/// Gets the interactive form dictionary (`/AcroForm`) of the document to change it, wherever it
+/// is written
+pub fn get_acroform_mut(doc: &mut Document) -> Option<&mut Dictionary> {
+    let root = doc.trailer.get(b"Root").ok()?.as_reference().ok()?;
+    match *doc.get_dictionary(root).ok()?.get(b"AcroForm").ok()? {
+        Object::Reference(id) => doc.get_object_mut(id).ok()?.as_dict_mut().ok(),
+        _ => doc
+            .get_object_mut(root)
+            .ok()?
+            .as_dict_mut()
+            .ok()?
+            .get_mut(b"AcroForm")
+            .ok()?
+            .as_dict_mut()
+            .ok(),
+    }
+}
+
+/// Looks up the font `font_name` of an appearance stream in the resources of the stream, then in
+/// the default resources (`/DR`) of the form
+pub fn find_font<'a>(
+    doc: &'a Document,
+    stream: &'a Stream,
+    font_name: &str,
+) -> Option<&'a Dictionary> {
+    let lookup = |resources: Option<&'a Object>| -> Option<&'a Dictionary> {
+        let (_, font) = doc
+            .dereference(font_entry(doc, resources, font_name)?)
+            .ok()?;
+        font.as_dict().ok()
+    };
+
+    lookup(stream.dict.get(b"Resources").ok())
+        .or_else(|| lookup(get_acroform(doc)?.get(b"DR").ok()))
+}
+
+/// Copies the font `font_name` from the default resources (`/DR`) of the form into the resources
+/// of an appearance stream, so that its `Tf` operator refers to an actual font.  Returns the new
+/// resources, or `None` if the stream already has the font or the form has no such font
+pub fn default_font_resources(
+    doc: &Document,
+    stream: &Stream,
+    font_name: &str,
+) -> Option<Dictionary> {
+    if font_entry(doc, stream.dict.get(b"Resources").ok(), font_name).is_some() {
+        return None;
+    }
+
+    let font = font_entry(doc, get_acroform(doc)?.get(b"DR").ok(), font_name)?;
+    Some(font_resources(doc, stream, font_name, font.clone()))
+}
+
+/// Gets the entry of the font `font_name` in a resource dictionary, which may be a reference
+pub fn font_entry<'a>(
+    doc: &'a Document,
+    resources: Option<&'a Object>,
+    font_name: &str,
+) -> Option<&'a Object> {
+    let (_, resources) = doc.dereference(resources?).ok()?;
+    let (_, fonts) = doc
+        .dereference(resources.as_dict().ok()?.get(b"Font").ok()?)
+        .ok()?;
+    fonts.as_dict().ok()?.get(font_name.as_bytes()).ok()
+}
+
+/// Writes a length of an appearance stream rounded to two decimals, which is more than precise
+/// enough and keeps the noise of `f32` arithmetic out of the stream
+pub fn number(value: f32) -> Object {
+    Object::Real((f64::from(value) * 100.0).round() / 100.0)
 }